@@ -13,6 +13,8 @@ use quote::quote;
 /// Included definitions:
 ///    
 /// - AstNode
+/// - CaseInsensitive
+/// - LexemePattern
 /// - GrammarToken
 pub fn generate_structures(names: &Vec<Token>) -> TokenStream {
     let ast_or_token = quote! {
@@ -21,6 +23,33 @@ pub fn generate_structures(names: &Vec<Token>) -> TokenStream {
             Ast(AstNode),
             Tok(Token),
         }
+
+        impl AstOrToken {
+            /// Gets the token matched by a terminal.
+            pub fn token(&self) -> Token {
+                match self {
+                    AstOrToken::Tok(tok) => tok.clone(),
+                    AstOrToken::Ast(node) => panic!("Expected a token, found {:?}", node.Type),
+                }
+            }
+
+            /// Gets the node built by a rule.
+            pub fn node(&self) -> AstNode {
+                match self {
+                    AstOrToken::Ast(node) => node.clone(),
+                    AstOrToken::Tok(tok) => panic!("Expected a rule, found token `{}`", tok.lexeme),
+                }
+            }
+
+            /// Gets the value built by a rule's action (`rule -> Type := ... => { ... };`).
+            /// Gives back `None` for tokens, and for nodes without a value of type `T`.
+            pub fn value<T: Clone + 'static>(&self) -> Option<T> {
+                match self {
+                    AstOrToken::Ast(node) => node.value.as_ref().and_then(|value| value.downcast_ref::<T>()).cloned(),
+                    AstOrToken::Tok(_) => None,
+                }
+            }
+        }
     };
     // Generate the abstract syntax tree info first.
     let ast_info = quote! {
@@ -28,20 +57,106 @@ pub fn generate_structures(names: &Vec<Token>) -> TokenStream {
         pub struct AstNode {
             pub Type: GrammarToken,
             pub child: Vec<Result<AstOrToken, ParserError>>, 
+            // Labelled ranges of `child`, as (label, start, end).
+            pub labels: Vec<(&'static str, usize, usize)>,
+            // The value built by the rule's action, if it has one.
+            pub value: Option<Rc<dyn Any>>,
+            // Which of the rule's options matched, counting from zero.
+            pub option: usize,
         }
         impl AstNode {
             pub fn new(tok: GrammarToken, child: Vec<Result<AstOrToken, ParserError>>) -> AstNode {
                 AstNode {
                     Type: tok,
                     child: child,
+                    labels: vec![],
+                    value: None,
+                    option: 0,
+                }
+            }
+
+            /// Gets the first child captured by a label in the grammar (`name:ident`).
+            pub fn get(&self, label: &str) -> Option<&AstOrToken> {
+                self.get_all(label).into_iter().next()
+            }
+
+            /// Gets the error a rule recovered from (`recover_to(...)`), if it had to.
+            /// Nodes made by recovering hold the error as their only child.
+            pub fn error(&self) -> Option<&ParserError> {
+                self.child.iter().find_map(|child| child.as_ref().err())
+            }
+
+            /// Gets every error recovered from in this node and the nodes under it,
+            /// in the order they appear.
+            pub fn errors(&self) -> Vec<&ParserError> {
+                let mut errors = vec![];
+                for child in &self.child {
+                    match child {
+                        Ok(AstOrToken::Ast(node)) => errors.extend(node.errors()),
+                        Ok(AstOrToken::Tok(_)) => {}
+                        Err(err) => errors.push(err),
+                    }
                 }
+                errors
             }
 
+            /// Gets every child captured by a label, for labels on groups or repetitions.
+            pub fn get_all(&self, label: &str) -> Vec<&AstOrToken> {
+                self.labels
+                    .iter()
+                    .filter(|(name, _, _)| *name == label)
+                    .flat_map(|(_, start, end)| self.child[*start..*end].iter())
+                    .filter_map(|child| child.as_ref().ok())
+                    .collect()
+            }
+
+            /// Gets every child captured by a label among `labels`, which the typed tree
+            /// narrows down to a single match of a group.
+            fn get_among(&self, label: &str, labels: &[(&'static str, usize, usize)]) -> Vec<&AstOrToken> {
+                labels
+                    .iter()
+                    .filter(|(name, _, _)| *name == label)
+                    .flat_map(|(_, start, end)| self.child[*start..*end].iter())
+                    .filter_map(|child| child.as_ref().ok())
+                    .collect()
+            }
+
+            /// Finds each match of a group among `labels`, as the option that matched and
+            /// the labels recorded while matching it. Each option of a group with a type of
+            /// its own records one of `options` once it matches, after everything inside it.
+            fn group_matches(&self, options: &[&str], labels: &[(&'static str, usize, usize)]) -> Vec<(usize, Vec<(&'static str, usize, usize)>)> {
+                let mut matches = vec![];
+                let mut from = 0;
+                for (at, (name, start, end)) in labels.iter().enumerate() {
+                    if let Some(option) = options.iter().position(|option| option == name) {
+                        let inside = labels[from..at]
+                            .iter()
+                            .filter(|label| *start <= label.1 && label.2 <= *end)
+                            .cloned()
+                            .collect();
+                        matches.push((option, inside));
+                        from = at + 1;
+                    }
+                }
+                matches
+            }
         }
     };
 
+    // Wrappers used to tell `expect` that a string literal should ignore case,
+    // or is a pattern to check the lexeme against.
+    let case_insensitive = quote! {
+        #[derive(Debug, Clone)]
+        pub struct CaseInsensitive(pub &'static str);
+
+        #[derive(Debug, Clone)]
+        pub struct LexemePattern(pub &'static str);
+    };
+
     let error_type = generate_error_type();
 
+    let parse_state = generate_parse_state();
+
     let grammar_tokens = generate_grammar_tokens(names);
 
     let includes = generate_includes();
@@ -49,12 +164,27 @@ pub fn generate_structures(names: &Vec<Token>) -> TokenStream {
     quote! {
         #includes
         #error_type
+        #parse_state
         #ast_info
         #ast_or_token
+        #case_insensitive
         #grammar_tokens
     }
 }
 
+/// Wrap the types generated for each rule in a module of their own, so the
+/// typed tree never clashes with the rest of the parser.
+pub fn generate_typed_tree(types: &Vec<TokenStream>) -> TokenStream {
+    quote! {
+        #[allow(non_snake_case)]
+        pub mod typed {
+            use super::*;
+
+            #( #types )*
+        }
+    }
+}
+
 /// Iterate over the names of the token to generate names for them all
 /// in an enum-style.
 fn generate_grammar_tokens(names: &Vec<Token>) -> TokenStream {
@@ -72,7 +202,89 @@ fn generate_includes() -> TokenStream {
     quote! {
         use std::fmt;
         use std::any::Any;
-        use self::GrammarToken::*; // ?
+        use std::rc::Rc;
+        use std::cell::RefCell;
+    }
+}
+
+/// State kept while parsing, for reporting errors and making repairs. It lives
+/// in a thread local, so that `expect` can reach it without it being passed
+/// through every rule.
+fn generate_parse_state() -> TokenStream {
+    quote! {
+        #[derive(Debug, Clone, Default)]
+        struct ParseState {
+            // The furthest point a terminal failed to match at, and what was expected there.
+            farthest: usize,
+            expected: Vec<Expected>,
+            // How many insertions had been used getting to `farthest`. Insertions don't
+            // move the tracker, so this tells apart how far the parse got at one position.
+            farthest_used: usize,
+            // Where each rule that recovered (`recover_to`) got stuck, as `farthest`,
+            // `farthest_used` and `expected` were then.
+            recoveries: Vec<(usize, usize, Vec<Expected>)>,
+            // The repairs `parse_repaired` is trying.
+            repairs: Vec<Repair>,
+            // The insertions in `repairs` used up so far, by index. Each one stands in
+            // for a single missing terminal, until the parse backs up past it.
+            used: Vec<usize>,
+        }
+
+        /// A terminal (or expectation label) the parse wanted where it failed.
+        #[derive(Debug, Clone, PartialEq)]
+        struct Expected {
+            // How it is described in errors, such as "`;`".
+            what: String,
+            // Whether `parse_repaired` can insert it.
+            insertable: bool,
+            // Whether an expectation label (`@`) describes it instead.
+            hidden: bool,
+            // Whether `what` is a whole error message, such as a group matching too many
+            // times, which is reported as it is rather than as something expected.
+            complete: bool,
+        }
+
+        /// A repair to the input: inserting the terminal described by `insert` at
+        /// `pos`, or removing the token at `pos` if there is nothing to insert.
+        #[derive(Debug, Clone, PartialEq)]
+        struct Repair {
+            pos: usize,
+            insert: Option<String>,
+        }
+
+        thread_local! {
+            static PARSE_STATE: RefCell<ParseState> = RefCell::new(ParseState::default());
+        }
+
+        /// How many insertions the parse has used up. Taken along with the tracker's
+        /// position, so that backing up to it can give them back.
+        fn insertions_used() -> usize {
+            PARSE_STATE.with(|state| state.borrow().used.len())
+        }
+
+        /// Gives back the insertions used up since `insertions_used` gave `used`.
+        fn give_back_insertions(used: usize) {
+            PARSE_STATE.with(|state| state.borrow_mut().used.truncate(used));
+        }
+
+        /// Notes an error found at `at` that isn't about a missing terminal. Unless the
+        /// parse gets further, it is reported in place of what was expected there.
+        fn note_error(at: usize, message: &str) {
+            PARSE_STATE.with(|state| {
+                let mut state = state.borrow_mut();
+                if at > state.farthest || state.expected.is_empty() {
+                    state.farthest = at;
+                    state.farthest_used = state.used.len();
+                    state.expected.clear();
+                } else if at < state.farthest {
+                    return;
+                }
+                for e in state.expected.iter_mut() {
+                    e.hidden = true;
+                }
+                state.expected.push(Expected { what: message.to_string(), insertable: false, hidden: false, complete: true });
+            });
+        }
     }
 }
 
@@ -87,6 +299,12 @@ fn generate_error_type() -> TokenStream {
             Context: Vec<Token>,
             Line: i32,
             Children: Vec<ParserError>,
+            // Where the tracker was when the error was made.
+            Pos: usize,
+            // Fatal errors stop all backtracking, and end the parse.
+            Fatal: bool,
+            // The error given back by a fallible action, if that is what failed.
+            User: Option<Rc<dyn Any>>,
         }
 
         impl ParserError {
@@ -116,7 +334,47 @@ fn generate_error_type() -> TokenStream {
                     Context: context,
                     Line: line,
                     Children: children,
+                    Pos: pos as usize,
+                    Fatal: false,
+                    User: None,
+                }
+            }
+
+            // Wrap an error given back by a fallible action (`=>? { ... }` or `=>! { ... }`).
+            fn user<E: fmt::Display + 'static>(mut tracker: &mut TokenTracker, error: E, fatal: bool) -> ParserError {
+                let mut err = ParserError::new(&mut tracker, error.to_string(), vec![]);
+                err.Fatal = fatal;
+                err.User = Some(Rc::new(error));
+                err
+            }
+
+            /// The furthest point in the input reached by this error or any under it.
+            fn farthest(&self) -> usize {
+                self.Children.iter().map(|child| child.farthest()).fold(self.Pos, usize::max)
+            }
+
+            /// What went wrong, such as the rule that failed to match.
+            pub fn message(&self) -> &str {
+                &self.Rule
+            }
+
+            /// The line of the token the error was found at.
+            pub fn line(&self) -> i32 {
+                self.Line
+            }
+
+            /// Whether this error aborted the parse.
+            pub fn is_fatal(&self) -> bool {
+                self.Fatal
+            }
+
+            /// Finds the error given back by an action, if it is of type `E`.
+            /// Errors from further down the call stack are checked as well.
+            pub fn user_error<E: 'static>(&self) -> Option<&E> {
+                if let Some(found) = self.User.as_ref().and_then(|user| user.downcast_ref::<E>()) {
+                    return Some(found);
                 }
+                self.Children.iter().find_map(|child| child.user_error::<E>())
             }
 
             /// A version of format that is smaller for use in the call stack.
@@ -13,13 +13,14 @@
 //! I see the &mut tr's in my sleep.
 //!             Send help.
 
-use proc_macro2::TokenStream;
+use proc_macro2::{TokenStream, TokenTree};
 use quote::{format_ident, quote};
 
-use crate::code_gen::generate_structures;
+use crate::code_gen::{generate_structures, generate_typed_tree};
 use crate::flat_stream::{give_group_deliminator, FlatStream, Token};
 use crate::token_tracker::{
-    get_as_string, get_token, give_max, mark, peek_as_string, reset, to_string, TokenTracker,
+    get_as_string, get_token, give_max, mark, peek_as_string, peek_token, reset, to_string,
+    TokenTracker,
 };
 
 #[derive(Debug, Clone)]
@@ -27,8 +28,145 @@ pub struct Collector {
     pub rules: Vec<TokenStream>,
     pub terminals: Vec<TokenStream>,
     pub names: Vec<Token>,
+    pub types: Vec<Option<TokenStream>>,
     //flattened: FlatStream,
     tracker: TokenTracker,
+    group_count: usize,
+    labels_seen: Vec<(String, Shape)>,
+    // How many groups deep the element being generated is.
+    nesting: usize,
+    pub typed: Vec<TokenStream>,
+    templates: Vec<Template>,
+    // Each use of a template made so far, as the template, its arguments, and the rule made for it.
+    instances: Vec<(String, Vec<String>, String)>,
+    // Rules ending in `recover_to(...)`, whose nodes might hold only an error.
+    recovering: Vec<String>,
+    // Rules marked with `~`, whose children are spliced into the rule using them.
+    silent: Vec<String>,
+    // For each group being generated, the label of the field it makes in the typed
+    // tree if it gets a type of its own, as its options then become fields too.
+    group_paths: Vec<Option<String>>,
+    // The names given to groups' types in the typed tree so far.
+    group_types: Vec<String>,
+}
+
+/// A rule that takes parameters (`list<X> := X (',' X)*;`). Templates don't
+/// become rules themselves; each use (`list<expr>`) becomes a rule of its own.
+#[derive(Debug, Clone)]
+struct Template {
+    name: String,
+    params: Vec<String>,
+    rule_type: Option<TokenStream>,
+    body: Vec<Token>,
+    silent: bool,
+}
+
+/// One option of a rule or group: the if statements that make it up, the
+/// action to run once they all match, and the labels found along the way.
+#[derive(Debug, Clone)]
+struct RuleOption {
+    stmts: Vec<TokenStream>,
+    action: Option<(TokenStream, ActionKind)>,
+    labels: Vec<(String, Shape)>,
+    fields: Vec<Field>,
+    // The keyword the option starts with, if any, used to name its variant in the typed tree.
+    keyword: Option<String>,
+}
+
+/// One option of a group with a type of its own: its fields, and the keyword
+/// it starts with, if any.
+#[derive(Debug, Clone)]
+struct GroupOption {
+    fields: Vec<Field>,
+    keyword: Option<String>,
+}
+
+/// A field of a rule's type in the typed tree, made from one element of an option.
+#[derive(Debug, Clone)]
+struct Field {
+    // The label holding the element's children; unlabelled elements are given one.
+    label: String,
+    name: String,
+    shape: Shape,
+    implicit: bool,
+}
+
+/// How an action's block is treated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ActionKind {
+    /// `=> { ... }`: The block is the value.
+    Value,
+    /// `=>? { ... }`: The block gives a `Result`, and an `Err` fails the option.
+    Fallible,
+    /// `=>! { ... }`: The block gives a `Result`, and an `Err` aborts the parse.
+    Aborting,
+}
+
+/// The shape of what a labelled element leaves in the tree, which decides what
+/// its label is bound to inside of an action.
+#[derive(Debug, Clone)]
+enum Shape {
+    /// Lookahead; nothing at all. Bound to `()`.
+    Nothing,
+    /// A single terminal. Bound to a `Token`.
+    Token,
+    /// A single rule. Bound to the rule's value if it has a type, or its `AstNode` if not.
+    Rule(String),
+    /// `?` and `[ ]`. Bound to an `Option`.
+    Optional(Box<Shape>),
+    /// `*`, `+`, `{n,m}` and lists. Bound to a `Vec`.
+    Repeated(Box<Shape>),
+    /// A group holding one terminal or rule next to string and char literals, as in
+    /// `(',' expr)`. Each match leaves `stride` children, and the one at `index` is kept.
+    Picked {
+        stride: usize,
+        index: usize,
+        inner: Box<Shape>,
+        label: Option<String>,
+    },
+    /// Any other group, as in `(a ',' b)` or `(a | b)`. Bound to a `Vec<AstOrToken>` of
+    /// the children, but the typed tree gives it a type of its own, called `name`. Each
+    /// option records a label once it matches, named after `path` (the group's label).
+    Group {
+        path: String,
+        name: String,
+        options: Vec<GroupOption>,
+    },
+    /// Anything else. Bound to a `Vec<AstOrToken>` of the children.
+    Children,
+}
+
+impl Shape {
+    /// Wraps the shape for an element around it that can match it many times
+    /// (`repeated`) or not at all. Shapes that are already optional or repeated
+    /// are kept where they can be, as `get_all` gathers every match in one list.
+    fn wrap(&mut self, repeated: bool) {
+        let inner = std::mem::replace(self, Shape::Nothing);
+        *self = match (inner, repeated) {
+            (Shape::Optional(inner), true) => Shape::Repeated(inner),
+            (inner @ Shape::Optional(_), false) | (inner @ Shape::Repeated(_), _) => inner,
+            (inner, true) => Shape::Repeated(Box::new(inner)),
+            (inner, false) => Shape::Optional(Box::new(inner)),
+        };
+    }
+
+    /// The group with a type of its own the shape holds, if any, through `?` and repetition.
+    fn group(&self) -> Option<&Shape> {
+        match self {
+            Shape::Group { .. } => Some(self),
+            Shape::Optional(inner) | Shape::Repeated(inner) => inner.group(),
+            _ => None,
+        }
+    }
+
+    /// The same as `group`, but mutable, for naming the group's type.
+    fn group_mut(&mut self) -> Option<&mut Shape> {
+        match self {
+            Shape::Group { .. } => Some(self),
+            Shape::Optional(inner) | Shape::Repeated(inner) => inner.group_mut(),
+            _ => None,
+        }
+    }
 }
 
 impl Collector {
@@ -37,6 +175,7 @@ impl Collector {
         let rules = vec![];
         let terminals = vec![];
         let names = vec![];
+        let types = vec![];
         let flattened = FlatStream::new(stream);
         let tracker = TokenTracker::new(&flattened);
 
@@ -44,7 +183,18 @@ impl Collector {
             rules,
             terminals,
             names,
+            types,
             tracker,
+            group_count: 0,
+            labels_seen: vec![],
+            nesting: 0,
+            typed: vec![],
+            templates: vec![],
+            instances: vec![],
+            recovering: vec![],
+            silent: vec![],
+            group_paths: vec![],
+            group_types: vec![],
         }
     }
 
@@ -57,10 +207,21 @@ impl Collector {
 
         while mark(&self.tracker) < give_max(&self.tracker) {
             // Assume each rule will contain the following format:
-            // name ':=' (rhs)
+            // ['~'] name ['<' params '>'] ['->' type] ':=' (rhs)
 
+            let silent = peek_as_string(&mut self.tracker)? == "~";
+            if silent {
+                let _tilde = get_token(&mut self.tracker)?;
+            }
             let name = get_token(&mut self.tracker)?;
-            self.names.push(name);
+            let params = self.take_params()?;
+            let rule_type = self.take_rule_type()?;
+            if silent && rule_type.is_some() {
+                return Err(format!(
+                    "Rule `{}` is spliced into the rules using it (`~`), so it can't have a type",
+                    to_string(name)?
+                ));
+            }
             let colon_check = get_as_string(&mut self.tracker)?; // Should always be a colon
             let equ_check = get_as_string(&mut self.tracker)?; // Should always be an equals sign.
 
@@ -75,13 +236,36 @@ impl Collector {
                 "Assignment statement was malformed"
             );
 
-            // Grab the rest of the rules
+            // Grab the rest of the rules. Groups are taken whole, as action blocks can hold ';'s.
             let mut temp = vec![];
-            while peek_as_string(&mut self.tracker)? != String::from(";") {
-                temp.push(get_token(&mut self.tracker)?);
+            let mut depth = 0;
+            while depth > 0 || peek_as_string(&mut self.tracker)? != ";" {
+                let tok = get_token(&mut self.tracker)?;
+                match to_string(tok.clone())?.as_str() {
+                    "BEGIN" => depth += 1,
+                    "END" => depth -= 1,
+                    _ => {}
+                }
+                temp.push(tok);
             }
 
-            mid_rules.push(temp.clone());
+            match params {
+                Some(params) => self.templates.push(Template {
+                    name: to_string(name)?,
+                    params,
+                    rule_type,
+                    body: temp.clone(),
+                    silent,
+                }),
+                None => {
+                    if silent {
+                        self.silent.push(to_string(name.clone())?);
+                    }
+                    self.names.push(name);
+                    self.types.push(rule_type);
+                    mid_rules.push(temp.clone());
+                }
+            }
             // Eat the trailing semi colon.
             let _null = get_token(&mut self.tracker)?;
         }
@@ -89,22 +273,60 @@ impl Collector {
         // Adjust mid_rules to remove the blank list of rules
         mid_rules = mid_rules[1..].to_vec();
 
+        // Swap each use of a template for a rule made from it. The new rules are
+        // added to the end of the list, so any uses inside of them get expanded too.
+        let mut index = 0;
+        while index < mid_rules.len() {
+            let (expanded, made) = match self.expand_templates(mid_rules[index].clone()) {
+                Ok(v) => v,
+                Err(m) => {
+                    let err = format!("Error generating parser: {}", m);
+                    panic!("{}", err);
+                }
+            };
+            mid_rules[index] = expanded;
+            mid_rules.extend(made);
+            index += 1;
+        }
+
+        // Rules using a rule that recovers need to know it can, so find them all up front.
+        for (index, rule) in mid_rules.iter().enumerate() {
+            if self.recovery_start(rule).is_none() {
+                continue;
+            }
+            let name = to_string(self.names[index].clone())?;
+            if self.types[index].is_some() {
+                let err = format!(
+                    "Error generating parser: Rule `{}` has a type, so it can't use `recover_to`, as a recovered node has no value",
+                    name
+                );
+                panic!("{}", err);
+            }
+            self.recovering.push(name);
+        }
+
         // Now that each rule has been extracted, iterate over all
         // of them to generate the parser token stream.
         let mut index = 0;
         for rule in mid_rules {
             let name = self.names[index].clone();
-            let test = self.generate_rule(rule, name);
+            let test = self
+                .take_recovery(rule)
+                .and_then(|(rule, recovery)| Ok((self.generate_rule(rule, name.clone())?, recovery)));
             match test {
-                Ok(v) => {
+                Ok((v, recovery)) => {
                     // If okay, merge all the rules into one big rule for one vector slot.
-                    let individual_rules = quote! {
+                    let mut individual_rules = quote! {
                         let mut identifiers: Vec<Result<AstOrToken, ParserError>> = vec![];
+                        let mut labels: Vec<(&'static str, usize, usize)> = vec![];
                         let mut parser_errors: Vec<ParserError> = vec![];
                         #(#v)*
                         let err = ParserError::new(&mut tracker, "Parser failed to match rules".to_string(), parser_errors.clone());
                         return Err(err);
                     };
+                    if let Some(sync) = recovery {
+                        individual_rules = self.make_recovery(&name, individual_rules, sync);
+                    }
                     self.rules.push(individual_rules);
                 }
                 Err(m) => {
@@ -120,6 +342,7 @@ impl Collector {
 
         //println!("Running boilerplate generation. . . ");
         let boilerplate = generate_structures(&self.names);
+        let typed = generate_typed_tree(&self.typed);
         //println!("Boilerplate generated: {:}", boilerplate);
 
         ///// FINAL GLUE SECTION /////
@@ -133,6 +356,7 @@ impl Collector {
 
         Ok(quote! {
             #boilerplate
+            #typed
             #parser
             #expect
             #match_f
@@ -150,21 +374,57 @@ impl Collector {
         let mut tr = TokenTracker::new(&FlatStream::new_from_tokens(toks));
         let mut options = vec![];
 
+        self.labels_seen.clear();
         let current_options = self.rule_gen_interior(&mut tr, working_name.clone())?;
+        let rule_type = self.rule_type(&to_string(name.clone())?);
+        if rule_type.is_none() {
+            let typed = self.make_typed(&to_string(name.clone())?, &current_options);
+            self.typed.push(typed);
+        }
 
-        for item in current_options {
-            let composed = self.collect_options(item.clone(), name.clone());
+        for (index, item) in current_options.into_iter().enumerate() {
+            let action = match (&rule_type, &item.action) {
+                (Some(ty), Some((block, kind))) => self.make_action(ty, block, *kind, &item.labels),
+                (None, None) => quote!(return Ok(node);),
+                (Some(_), None) => {
+                    return Err(format!(
+                        "Rule `{}` has a type, so every option needs an action (`=> {{ ... }}`)",
+                        to_string(name)?
+                    ))
+                }
+                (None, Some(_)) => {
+                    return Err(format!(
+                        "Rule `{}` has an action, so it needs a type (`{} -> Type := ...`)",
+                        to_string(name.clone())?,
+                        to_string(name)?
+                    ))
+                }
+            };
+            let composed = self.collect_options(
+                item.stmts.clone(),
+                quote! {
+                    let mut node = AstNode::new(GrammarToken::#name, identifiers.clone());
+                    node.labels = labels.clone();
+                    node.option = #index;
+                    #action
+                },
+            );
+            let abort = self.make_abort_check();
             options.push(quote! {
                 let pos = mark(&mut tracker);
+                let used = insertions_used();
                 #composed
+                #abort
                 let err = ParserError::new(
                     &mut tracker,
-                    format!("Failed to match {:?}", #working_name ),
+                    format!("Failed to match {:?}", GrammarToken::#working_name ),
                     vec![identifiers.last().cloned().unwrap().err().unwrap()], // Dude. Why.
                 );
                 parser_errors.push(err);
                 reset(&mut tracker, pos);
+                give_back_insertions(used);
                 identifiers.clear();
+                labels.clear();
             })
         }
 
@@ -179,116 +439,63 @@ impl Collector {
         &mut self,
         mut tr: &mut TokenTracker,
         name: Token,
-    ) -> Result<Vec<Vec<TokenStream>>, String> {
+    ) -> Result<Vec<RuleOption>, String> {
         let mut output = vec![];
 
         while mark(&mut tr) < give_max(&mut tr) {
             let mut current_option = vec![];
+            let mut action = None;
+            let label_mark = self.labels_seen.len();
+            let mut fields = vec![];
+            let mut keyword = None;
+            let mut cuts = vec![];
 
             // Loop over list until an 'or' symbol (the '|') is hit or the end of the token list is reached.
             while !peek_as_string(&mut tr).is_err()            // *.is_err needs to be first for short-circuit evaluation.
             && peek_as_string(&mut tr)? != String::from("|")
             {
                 println!("Looking at: {:}", peek_as_string(&mut tr)?);
-                // Look for the token sequence of #( and call the requisite subroutine.
-                if peek_as_string(&mut tr)? == String::from("#") {
-                    let pos = mark(&mut tr);
-                    let _temp = get_token(&mut tr)?; // Eat the '#' token, we dont need it.
-                                                     // Pseudo two token lookahead
-
-                    //// MATCH IDENTIFIERS ////
-                    if peek_as_string(&mut tr)? == String::from("BEGIN") {
-                        //reset(&mut tr, pos);
-                        //output.push(self.make_identifier_option(tr.clone(), name.clone())); // Make an option that was given as an identifier
-                        current_option.push(self.make_identifier_option(tr.clone())); // Make an option that was given as an identifier
-
-                        // There may be more identifiers in sequence after this, so skip to the end of those...
-                        while peek_as_string(&mut tr)? != String::from("|")
-                            && peek_as_string(&mut tr)? != String::from("END")
-                        {
-                            let _null = get_as_string(&mut tr)?;
-                        }
-
-                        // Eat the end token
-                        let _null = get_token(&mut tr);
-                        continue;
-                    } else {
-                        // Was not an identifier.
-                        reset(&mut tr, pos);
+                if let Some(found) = self.take_action(tr)? {
+                    action = Some(found);
+                    if peek_as_string(tr).is_ok_and(|next| next != "|") {
+                        return Err("Nothing can come after an action (`=> { ... }`) in an option".to_string());
                     }
+                    continue;
+                }
+                if peek_as_string(tr)? == "^" {
+                    let _cut = get_token(tr)?;
+                    cuts.push(current_option.len());
+                    continue;
                 }
 
-                // This is the situation with paren groups.
-                if peek_as_string(&mut tr)? == String::from("BEGIN") {
-                    let mut internals = vec![];
-
-                    // Grab the paren group to keep for later
-                    let paren_group = get_token(&mut tr)?;
-                    //println!("Parent paren group is {:?}", paren_group);
-
-                    let mut end_count = 1;
-                    while end_count != 0 {
-                        let end = get_token(&mut tr)?;
-
-                        if to_string(end.clone())? == String::from("BEGIN") {
-                            end_count += 1;
-                        } else if to_string(end.clone())? == String::from("END") {
-                            end_count -= 1;
+                // The elements of a rule become fields in the typed tree, and so do the
+                // elements of a group with a type of its own, labelled after the group.
+                let prefix = match (self.nesting, self.group_paths.last()) {
+                    (0, _) => Some("#".to_string()),
+                    (_, Some(Some(path))) => Some(format!("{}.", self.group_label(path, output.len()))),
+                    _ => None,
+                };
+                let field = match prefix {
+                    Some(prefix) => {
+                        if self.nesting == 0 && current_option.is_empty() {
+                            keyword = self.take_keyword(&mut tr.clone());
                         }
-
-                        internals.push(end.clone());
-                        //println!("Internal object is {:?}", end);
+                        self.make_field(&mut tr.clone(), &format!("{}{}", prefix, current_option.len()))?
                     }
-
-                    // Eat trailing end token.
-                    let _end = internals.pop().unwrap();
-                    //println!("End is {:?}", _end);
-
-                    // Iterate to the end of the group, and see look for the modifier token.
-                    let mod_pos = mark(&mut tr);
-                    let modifier_token: Token;
-                    if peek_as_string(&mut tr).is_ok()
-                        && (peek_as_string(&mut tr)? == String::from("*")
-                            || peek_as_string(&mut tr)? == String::from("+"))
-                    {
-                        // Check that there is actually a token there.
-                        modifier_token = get_token(&mut tr)? // If not, don't walk off the end and cause a panic.
-                    } else {
-                        reset(&mut tr, mod_pos); // Back the parser up to before the mod check
-                        modifier_token = _end.clone();
-                    }
-
-                    //println!("Modifier is {:?}", modifier_token.clone());
-                    //println!("Internals are {:?}", internals);
-
-                    // options.push(self.make_paren_group_option(paren_group.clone(),
-                    let nested_items = self.make_paren_group_option(
-                        paren_group.clone(),
-                        internals.clone(),
-                        modifier_token.clone(),
-                        name.clone(),
-                    )?;
-
-                    let mut out = quote! {};
-                    for internal in nested_items {
-                        out = quote! {
-                            #out
-                            #internal
-                        }
+                    None => None,
+                };
+                let path = field.as_ref().filter(|field| field.shape.group().is_some()).map(|field| field.label.clone());
+                self.group_paths.push(path);
+                let stmt = self.make_element(tr, name.clone());
+                self.group_paths.pop();
+                let mut stmt = stmt?;
+                if let Some(field) = field {
+                    if field.implicit {
+                        stmt = self.make_labelled(stmt, &field.label);
                     }
-                    current_option.push(out);
-
-                    continue;
+                    fields.push(field);
                 }
-
-                // We need to alter this in such a way that
-                // paren groups can be interpolated within normal symbols.
-                // Right now, normal symbols are being pushed to current_option and
-                // being turned into if statements later.
-                // If we turn everything into it's relevant if statement and interpolate them
-                // together later (when '|' or ';' is seen), we can work around this.
-                //current_option.push(get_token(&mut tr)?);
-                current_option.push(self.make_single_if_statement(get_token(&mut tr)?));
+                current_option.push(stmt);
             }
 
             if !peek_as_string(&mut tr).is_err() {
@@ -296,394 +503,2767 @@ impl Collector {
                 let _null = get_token(&mut tr);
             }
 
-            output.push(current_option);
+            // Everything after a cut becomes one element, starting from the last cut.
+            for at in cuts.into_iter().rev() {
+                let rest = current_option.split_off(at);
+                let cut = self.make_cut(rest);
+                current_option.push(cut);
+            }
+
+            output.push(RuleOption {
+                stmts: current_option,
+                action,
+                labels: self.labels_seen[label_mark..].to_vec(),
+                fields,
+                keyword,
+            });
         }
 
         Ok(output.clone())
     }
 
-    /// This function is a wrapper for options that arrive with in groups of parenthesis.
-    /// This allows us to add modifiers as we see fit, or even create matching
-    /// subgroups.
-    ///
-    /// Basically, its more complexity and I'm not sure if I know what to do here.
-    fn make_paren_group_option(
-        &mut self,
-        group: Token,
-        internals: Vec<Token>,
-        modifier: Token,
-        name: Token,
-    ) -> Result<Vec<TokenStream>, String> {
-        // Im really not sure how to do this. . .
-
-        // Make a new token stream out of the internals.
-        let mut tr = TokenTracker::new(&FlatStream::new_from_tokens(internals.clone()));
-
-        // Iterate over the internals; generate statements for each.
+    /// Makes the code for the next element of an option: a `#()` token literal,
+    /// a group, or a single item, along with any modifier that follows it.
+    /// Elements can be prefixed by a label (`name:`) and the lookahead predicates
+    /// `&` and `!`, and followed by a separated list operator (`%` or `%+`).
+    fn make_element(&mut self, tr: &mut TokenTracker, name: Token) -> Result<TokenStream, String> {
+        // A label (`name:ident`) captures whatever element comes next.
+        if let Some(label) = self.take_label(tr)? {
+            let shape = self.element_shape(&mut tr.clone(), &label)?;
+            self.labels_seen.push((label.clone(), shape));
+            let stmt = self.make_element(tr, name)?;
+            return Ok(self.make_labelled(stmt, &label));
+        }
 
-        let mut all_options = vec![];
-        let mut current_option = vec![];
-        while mark(&mut tr) < give_max(&mut tr) {
-            if peek_as_string(&mut tr)? == String::from("|") {
-                all_options.push(current_option.clone());
-                current_option.clear();
+        // Labels inside of a dropped element or lookahead never have anything to hold.
+        let label_mark = self.labels_seen.len();
 
-                // Eat trailing '|'
-                let _null = get_token(&mut tr);
-            }
+        // A dropped element (`~';'`) is matched, but leaves nothing in the tree.
+        if peek_as_string(tr)? == "~" {
+            let _tilde = get_token(tr)?;
+            let stmt = self.make_element(tr, name)?;
+            self.wrap_labels(label_mark, false);
+            return Ok(self.make_dropped(stmt));
+        }
 
-            // If there's a group, parse it as another group.
-            if peek_as_string(&mut tr)? == String::from("BEGIN") {
-                let mut internals = vec![];
+        // Lookahead predicates apply to whatever element comes next.
+        if peek_as_string(tr)? == "&" || peek_as_string(tr)? == "!" {
+            let positive = get_as_string(tr)? == "&";
+            let stmt = self.make_element(tr, name)?;
+            self.wrap_labels(label_mark, false);
+            return Ok(self.make_lookahead(stmt, positive));
+        }
 
-                // Grab the paren group to keep for later
-                let paren_group = get_token(&mut tr)?;
-                //println!("Parent paren group is {:?}", paren_group);
+        let stmt = self.make_item(tr, name)?;
+        if peek_as_string(tr).ok().as_deref() == Some("%") {
+            self.wrap_labels(label_mark, true);
+        }
+        let stmt = self.make_separated_list(tr, stmt)?;
+        match self.take_expectation(tr)? {
+            Some(expected) => Ok(self.make_expectation(stmt, &expected)),
+            None => Ok(stmt),
+        }
+    }
 
-                let mut end_count = 1;
-                while end_count != 0 {
-                    let end = get_token(&mut tr)?;
+    /// Checks for an expectation label after an element (`expr @ "an expression"`),
+    /// and eats it if there is one.
+    fn take_expectation(&self, tr: &mut TokenTracker) -> Result<Option<String>, String> {
+        if peek_as_string(tr).ok().as_deref() != Some("@") {
+            return Ok(None);
+        }
+        let _at = get_token(tr)?;
+        match get_token(tr)? {
+            Token::Literal(lit) if lit.to_string().starts_with('"') && lit.to_string().ends_with('"') => {
+                Ok(Some(self.string_value(&lit.to_string())))
+            }
+            other => Err(format!(
+                "Expected a string after `@` to describe what was expected, found `{}`",
+                to_string(other)?
+            )),
+        }
+    }
 
-                    if to_string(end.clone())? == String::from("BEGIN") {
-                        end_count += 1;
-                    } else if to_string(end.clone())? == String::from("END") {
-                        end_count -= 1;
-                    }
+    /// Wraps an element so that if it fails, its error reads "Expected" followed
+    /// by the description given to it, rather than whatever the element itself
+    /// would have said. Fatal errors are left alone, as they point at a failure
+    /// further in.
+    fn make_expectation(&mut self, stmt: TokenStream, expected: &str) -> TokenStream {
+        self.group_count += 1;
+        let ok = format_ident!("expect_ok_{}", self.group_count);
+        let start = format_ident!("expect_start_{}", self.group_count);
+        let seen = format_ident!("expect_seen_{}", self.group_count);
+        let message = format!("Expected {}", expected);
 
-                    internals.push(end.clone());
-                    //println!("Internal object is {:?}", end);
+        quote! {
+            let #start = mark(&mut tracker);
+            let #seen = PARSE_STATE.with(|state| {
+                let state = state.borrow();
+                if state.farthest == #start { state.expected.len() } else { 0 }
+            });
+            let mut #ok = false;
+            #stmt {
+                #ok = true;
+            }
+            if !#ok {
+                let fatal = matches!(identifiers.last(), Some(Err(e)) if e.Fatal);
+                if !fatal {
+                    identifiers.pop();
+                    identifiers.push(Err(ParserError::new(&mut tracker, #message.to_string(), vec![])));
                 }
+                // If the item failed before getting anywhere, the label describes
+                // what it expected there instead of its terminals.
+                PARSE_STATE.with(|state| {
+                    let mut state = state.borrow_mut();
+                    if state.farthest == #start {
+                        for e in state.expected.iter_mut().skip(#seen) {
+                            e.hidden = true;
+                        }
+                        if !state.expected.iter().any(|e| e.what == #expected) {
+                            state.expected.push(Expected { what: #expected.to_string(), insertable: false, hidden: false, complete: false });
+                        }
+                    }
+                });
+            }
+            if #ok
+        }
+    }
 
-                // Eat trailing end token.
-                let _end = internals.pop().unwrap();
-                //println!("End is {:?}", _end);
+    /// Makes the code for a single item and its modifier, without any of the
+    /// lookahead or list operators around it.
+    fn make_item(&mut self, tr: &mut TokenTracker, name: Token) -> Result<TokenStream, String> {
+        // Look for the token sequence of #( and call the requisite subroutine.
+        if peek_as_string(tr)? == "#" {
+            let pos = mark(tr);
+            let _temp = get_token(tr)?; // Eat the '#' token, we dont need it.
+                                        // Pseudo two token lookahead
+
+            //// MATCH IDENTIFIERS ////
+            if peek_as_string(tr)? == "BEGIN" {
+                let stmt = self.make_identifier_option(tr)?; // Make an option that was given as an identifier
+                return self.make_modified_item(tr, stmt);
+            } else {
+                // Was not an identifier.
+                reset(tr, pos);
+            }
+        }
 
-                // Iterate to the end of the group, and see look for the modifier token.
-                let mod_pos = mark(&mut tr);
-                let modifier_token: Token;
-                if peek_as_string(&mut tr).is_ok()
-                    && (peek_as_string(&mut tr)? == String::from("*")
-                        || peek_as_string(&mut tr)? == String::from("+"))
-                {
-                    // Check that there is actually a token there.
-                    modifier_token = get_token(&mut tr)? // If not, don't walk off the end and cause a panic.
-                } else {
-                    reset(&mut tr, mod_pos); // Back the parser up to before the mod check
-                    modifier_token = _end.clone();
-                }
+        // This is the situation with paren groups.
+        if peek_as_string(tr)? == "BEGIN" {
+            let mut internals = vec![];
 
-                //println!("Modifier is {:?}", modifier_token.clone());
-                //println!("Internals are {:?}", internals);
+            // Grab the paren group to keep for later
+            let paren_group = get_token(tr)?;
+            //println!("Parent paren group is {:?}", paren_group);
 
-                // options.push(self.make_paren_group_option(paren_group.clone(),
-                let nested_items = self.make_paren_group_option(
-                    paren_group.clone(),
-                    internals.clone(),
-                    modifier_token.clone(),
-                    name.clone(),
-                )?;
+            let mut end_count = 1;
+            while end_count != 0 {
+                let end = get_token(tr)?;
 
-                let mut out = quote! {};
-                for internal in nested_items {
-                    out = quote! {
-                        #out
-                        #internal
-                    }
+                if to_string(end.clone())? == "BEGIN" {
+                    end_count += 1;
+                } else if to_string(end.clone())? == "END" {
+                    end_count -= 1;
                 }
-                current_option.push(out);
 
-                // The existence of this continue is questionable.
-                continue;
+                internals.push(end.clone());
+                //println!("Internal object is {:?}", end);
             }
 
-            current_option.push(self.make_identifier_pusher(get_token(&mut tr)?));
-        }
+            // Eat trailing end token.
+            let _end = internals.pop().unwrap();
+            //println!("End is {:?}", _end);
 
-        all_options.push(current_option);
+            // Iterate to the end of the group, and see look for the modifier token.
+            let modifier = self.take_modifier(tr, &["*", "+", "?", "{"])?;
 
-        let mut output = vec![];
-        let mut inside = quote!();
-        let mut out = quote!();
-        let fb_p = format_ident!("fallback_pos_{:}", internals.len());
-        let fb_s = format_ident!("fallback_size_{:}", internals.len());
-        for item in all_options {
-            if to_string(modifier.clone())? == String::from("*") {
-                inside = quote! {
-                    #inside
-                    #(
-                        // Just trust me on this one.
-                        #item
-                        if identifiers.last().cloned().unwrap().is_ok() {
-                            get_got = true;
-                        }
-                    )*
-                    if get_got {
-                        continue;
-                    }
-                    get_got = false;
-                };
+            //println!("Modifier is {:?}", modifier.clone());
+            //println!("Internals are {:?}", internals);
+
+            // options.push(self.make_paren_group_option(paren_group.clone(),
+            let nested_items = self.make_paren_group_option(
+                paren_group.clone(),
+                internals.clone(),
+                &modifier.unwrap_or_default(),
+                name.clone(),
+            )?;
+
+            let mut out = quote! {};
+            for internal in nested_items {
                 out = quote! {
-                    loop {
-                        let #fb_p = mark(&mut tracker);
-                        let #fb_s = identifiers.len();
-                        let mut get_got = false;
-                        #inside
-                        if !get_got {
-                            while identifiers.len() != #fb_s {identifiers.pop();}
-                            reset(&mut tracker, #fb_p);
-                            break;
-                        }
-                    }
+                    #out
+                    #internal
                 }
-            } else if give_group_deliminator(group.clone()) == String::from("[")
-                || to_string(modifier.clone())? == String::from("?")
-            {
-                inside = quote! {
-                    #inside
-                    #(
-                        #item
-                        if identifiers.last().cloned().unwrap().is_err() {
-                            // Back up the marker by one
-                            //let back_one = mark(&mut tracker) - 1;
-                            //reset(&mut tracker, back_one);
-                            identifiers.pop(); // Pop the failed option and move on
-                        }
-                    )*
-                };
-                out = quote! {
-                    #inside
+            }
+            return Ok(out);
+        }
+
+        // We need to alter this in such a way that
+        // paren groups can be interpolated within normal symbols.
+        // Right now, normal symbols are being pushed to current_option and
+        // being turned into if statements later.
+        // If we turn everything into it's relevant if statement and interpolate them
+        // together later (when '|' or ';' is seen), we can work around this.
+        //current_option.push(get_token(&mut tr)?);
+        let stmt = self.make_single_if_statement(get_token(tr)?)?;
+        self.make_modified_item(tr, stmt)
+    }
+
+    /// Checks for a label in front of the next element (`name:ident`), and eats
+    /// it if there is one. A lone `:` is needed, so paths like `a::b` are left alone.
+    fn take_label(&self, tr: &mut TokenTracker) -> Result<Option<String>, String> {
+        let pos = mark(tr);
+        if let Token::Ident(label) = get_token(tr)? {
+            // A lone ':' makes a label, but '::' is part of a path.
+            if let Ok(Token::Punct(p)) = get_token(tr) {
+                if p.as_char() == ':' && peek_as_string(tr).ok().as_deref() != Some(":") {
+                    return Ok(Some(label.to_string()));
                 }
-            } else {
-                inside = quote! {
-                    #inside
-                    #(
-                        #item
-                        if identifiers.last().cloned().unwrap().is_err() {
-                            let err = ParserError(
-                                &mut tracker,
-                                "Failed to match rule.".to_string(),
-                                vec![identifiers.last().cloned().unwrap().err().unwrap()],
-                            );
-                            return Err(err);
-                        }
-                    )*
-                };
-                out = quote!(#inside);
             }
         }
+        reset(tr, pos);
+        Ok(None)
+    }
 
-        output.push(out);
-        Ok(output)
+    /// Wraps the shapes of the labels seen since `from` for the element holding them.
+    fn wrap_labels(&mut self, from: usize, repeated: bool) {
+        for (_, shape) in self.labels_seen[from..].iter_mut() {
+            shape.wrap(repeated);
+        }
     }
 
-    /// Making an identifier option is similar, but different.
-    /// For starters, an identifier matches a tokens *.identifier field through
-    /// its given type. This means that we need to grab a group of tokens, which
-    /// while more convenient, means we have to change how we proceed.
-    fn make_identifier_option(&mut self, mut tracker: TokenTracker) -> TokenStream {
-        // So for this to work, the contents inside of the parentheses are all going to be
-        // equivalent to whatever the Token.identifier field is. For us, that is ::TokenType.
-        // So if something is within the parens, it is matched and will be overloaded for later.
+    /// Wraps an element in a label. When the element matches, the range of
+    /// children it added is recorded in `labels`, so the node can hand them
+    /// back by name with `AstNode::get`.
+    fn make_labelled(&mut self, stmt: TokenStream, label: &str) -> TokenStream {
+        self.group_count += 1;
+        let start = format_ident!("label_start_{}", self.group_count);
+        let ok = format_ident!("label_ok_{}", self.group_count);
+
+        quote! {
+            let #start = identifiers.len();
+            let mut #ok = false;
+            #stmt {
+                #ok = true;
+            }
+            if #ok {
+                labels.push((#label, #start, identifiers.len()));
+            }
+            if #ok
+        }
+    }
 
-        // We can do this easily by grabbing the entire group marked by begin.
-        while peek_as_string(&mut tracker).unwrap() != String::from("BEGIN") {
-            let _null = get_token(&mut tracker);
+    /// Works out the shape of the element the tracker is sitting on, without
+    /// generating anything for it. `path` is the label the element is recorded
+    /// under, which groups name their own labels after. The tracker should be a
+    /// clone, as this walks it.
+    fn element_shape(&self, tr: &mut TokenTracker, path: &str) -> Result<Shape, String> {
+        let _label = self.take_label(tr)?;
+        let first = peek_as_string(tr)?;
+        if first == "&" || first == "!" || first == "~" {
+            let _predicate = get_token(tr)?;
+            let _inner = self.element_shape(tr, path)?;
+            return Ok(Shape::Nothing);
         }
 
-        // Return the token group that contains all the information.
+        let tok = get_token(tr)?;
+        let mut shape = match tok.clone() {
+            Token::Punct(p) if p.as_char() == '#' => {
+                let _group = get_token(tr)?;
+                self.skip_group(tr)?;
+                Shape::Token
+            }
+            Token::Punct(_) | Token::Literal(_) => Shape::Token,
+            // Spliced rules leave however many children they matched.
+            Token::Ident(i) if self.silent.contains(&i.to_string()) => Shape::Children,
+            Token::Ident(i) => Shape::Rule(i.to_string()),
+            Token::Begin(_, _) => {
+                let inner = self.group_shape(tr, path)?;
+                if give_group_deliminator(tok) == "[" && !matches!(peek_as_string(tr).as_deref(), Ok("*" | "+" | "{")) {
+                    Shape::Optional(Box::new(inner))
+                } else {
+                    inner
+                }
+            }
+            Token::End(_, _) => Shape::Children,
+        };
+
+        shape = match self.take_modifier(tr, &["*", "+", "?", "{"])?.as_deref() {
+            Some("?") => Shape::Optional(Box::new(shape)),
+            Some(_) => Shape::Repeated(Box::new(shape)),
+            None => shape,
+        };
+
+        // Walk over a list's separator as well, so the tracker ends up after the whole element.
+        if peek_as_string(tr).is_ok_and(|next| next == "%") {
+            let _percent = get_token(tr)?;
+            let _plus = self.take_modifier(tr, &["+"])?;
+            if get_as_string(tr)? == "#" {
+                let _group = get_token(tr)?;
+                self.skip_group(tr)?;
+            }
+            let _trailing = self.take_modifier(tr, &["?"])?;
+            shape = Shape::Repeated(Box::new(shape));
+        }
+        let _expected = self.take_expectation(tr)?;
+        Ok(shape)
+    }
 
-        let group = get_token(&mut tracker).unwrap();
+    /// Works out the shape of a group's internals, with the tracker sitting just
+    /// inside of it. A group holding a single terminal or rule among string and
+    /// char literals is `Picked`, so `(',' expr)*` gives the `expr`s, and so is a
+    /// group holding a single string or char literal, so `[ '!' ]` gives the `'!'`.
+    /// Any other group gets a type of its own in the typed tree, with fields made
+    /// the same way a rule's are.
+    fn group_shape(&self, tr: &mut TokenTracker, path: &str) -> Result<Shape, String> {
+        let mut elements = vec![];
+        let mut options = vec![GroupOption { fields: vec![], keyword: None }];
+        let mut position = 0;
+        while peek_as_string(tr)? != "END" {
+            if peek_as_string(tr)? == "|" {
+                let _bar = get_token(tr)?;
+                options.push(GroupOption { fields: vec![], keyword: None });
+                position = 0;
+                continue;
+            }
+            if peek_as_string(tr)? == "^" {
+                let _cut = get_token(tr)?;
+                continue;
+            }
+            let option = options.len() - 1;
+            if position == 0 {
+                options[option].keyword = self.take_keyword(&mut tr.clone());
+            }
+            let fixed = self.is_fixed_terminal(&mut tr.clone());
+            let label = self.take_label(&mut tr.clone())?;
+            let implicit = format!("{}.{}", self.group_label(path, option), position);
+            let shape = self.element_shape(tr, label.as_ref().unwrap_or(&implicit))?;
+            options[option].fields.extend(self.shape_field(&shape, fixed, label.clone(), implicit));
+            elements.push((shape, fixed, label));
+            position += 1;
+        }
+        let _end = get_token(tr)?;
+
+        // Lookahead leaves no children behind, so it doesn't count towards the stride.
+        let counted: Vec<(Shape, bool, Option<String>)> = elements
+            .into_iter()
+            .filter(|(shape, _, _)| !matches!(shape, Shape::Nothing))
+            .collect();
+        let simple = counted
+            .iter()
+            .all(|(shape, _, _)| matches!(shape, Shape::Token | Shape::Rule(_)));
+        let mut kept: Vec<usize> = (0..counted.len()).filter(|i| !counted[*i].1).collect();
+        if kept.is_empty() && counted.len() == 1 {
+            kept.push(0);
+        }
+        if options.len() > 1 || !simple || kept.len() != 1 {
+            return Ok(Shape::Group {
+                path: path.to_string(),
+                name: String::new(),
+                options,
+            });
+        }
+        Ok(Shape::Picked {
+            stride: counted.len(),
+            index: kept[0],
+            inner: Box::new(counted[kept[0]].0.clone()),
+            label: counted[kept[0]].2.clone(),
+        })
+    }
 
-        let stmt = self.make_single_if_statement(group.clone());
+    /// The label each option of the group recorded under `path` records once it matches.
+    fn group_label(&self, path: &str, option: usize) -> String {
+        format!("#{}.{}", path.trim_start_matches('#'), option)
+    }
 
-        quote! {
-            //identifiers.clear();
-            //let pos = mark(&mut tracker);
-            //#stmt {
-                //return Ok(AstNode::new(#name, identifiers.clone()));
-            //}
-            //reset(&mut tracker, pos);
-            #stmt
+    /// Whether the element the tracker is sitting on is a string or char literal
+    /// with nothing else attached, which always matches the same text.
+    fn is_fixed_terminal(&self, tr: &mut TokenTracker) -> bool {
+        if let Ok(Some(_)) = self.take_label(tr) {
+            return false;
+        }
+        match get_token(tr) {
+            Ok(Token::Literal(lit)) => {
+                let text = lit.to_string();
+                (text.starts_with('"') || text.starts_with('\'')) && text.ends_with(['"', '\''])
+                    && !matches!(peek_as_string(tr).as_deref(), Ok("*" | "+" | "?" | "{" | "%"))
+            }
+            _ => false,
         }
     }
 
-    /// A function used to make a single if statement. Indent is used to make a unique
-    /// identifier in the case of a grammar such as the following:
-    ///
-    /// ``` expr := term '-' term ```
-    ///
-    /// where the grammar could otherwise become ambiguous.
-    ///
-    /// Additionally, the identifier used is returned for later use with the AstNode.
-    fn make_single_if_statement(&mut self, tok: Token) -> TokenStream {
-        let ident = self.make_identifier_pusher(tok.clone());
+    /// Works out the field an element of a rule's option becomes in the typed
+    /// tree, labelling it `implicit` if it has no label of its own. The tracker
+    /// should be a clone, as this walks it.
+    fn make_field(&self, tr: &mut TokenTracker, implicit: &str) -> Result<Option<Field>, String> {
+        let label = self.take_label(&mut tr.clone())?;
+        let fixed = self.is_fixed_terminal(&mut tr.clone());
+        let shape = self.element_shape(tr, label.as_deref().unwrap_or(implicit))?;
+        Ok(self.shape_field(&shape, fixed, label, implicit.to_string()))
+    }
 
-        quote! {
-            //identifiers.push(expect(&mut tracker, &#tok));
-            #ident
-            //if identifiers.last().cloned().unwrap().is_some() // What the fuck.
-            if identifiers.last().cloned().unwrap().is_ok() 
+    /// The field an element of the given shape becomes. Lookahead and plain string
+    /// and char literals don't become fields, unless they are labelled.
+    fn shape_field(&self, shape: &Shape, fixed: bool, label: Option<String>, implicit: String) -> Option<Field> {
+        if matches!(shape, Shape::Nothing) || (fixed && label.is_none()) {
+            return None;
         }
+        Some(match label {
+            Some(label) => Field {
+                name: label.clone(),
+                label,
+                shape: shape.clone(),
+                implicit: false,
+            },
+            None => Field {
+                name: self.shape_name(shape),
+                label: implicit,
+                shape: shape.clone(),
+                implicit: true,
+            },
+        })
     }
 
-    /// Helper function used to generate just the identifier to be pushed
-    /// when given a token.
-    fn make_identifier_pusher(&mut self, tok: Token) -> TokenStream {
-        quote! {
-            identifiers.push(expect(&mut tracker, &#tok));
+    /// The name given to an unlabelled field: the label or rule it holds, `token`, or `group`.
+    fn shape_name(&self, shape: &Shape) -> String {
+        match shape {
+            Shape::Rule(rule) => rule.clone(),
+            Shape::Picked { label: Some(label), .. } => label.clone(),
+            Shape::Token => "token".to_string(),
+            Shape::Optional(inner) | Shape::Repeated(inner) | Shape::Picked { inner, .. } => self.shape_name(inner),
+            Shape::Group { .. } => "group".to_string(),
+            Shape::Children | Shape::Nothing => "children".to_string(),
         }
     }
 
-    /// A recursive function used to collect a vector of TokenStream's into
-    /// one big nested TokenStream. Similar to macro expansion, except for
-    /// recursive rather than iterative
-    fn collect_options(&mut self, stmts: Vec<TokenStream>, name: Token) -> TokenStream {
-        // Base case:
-        if stmts.len() == 0 {
-            return quote! {
-                return Ok(AstNode::new(#name, identifiers.clone()));
-            };
+    /// Gets the keyword an option starts with (`"let" name:ident ...`), if it starts with one.
+    fn take_keyword(&self, tr: &mut TokenTracker) -> Option<String> {
+        // Dropped keywords (`~"let"`) still name the option.
+        if peek_as_string(tr).is_ok_and(|next| next == "~") {
+            let _tilde = get_token(tr);
         }
-        // otherwise:
+        match get_token(tr) {
+            Ok(Token::Literal(lit)) => {
+                let text = lit.to_string();
+                if !text.starts_with('"') || !text.ends_with('"') {
+                    return None;
+                }
+                let word = self.string_value(&text);
+                let is_word = word.chars().next().is_some_and(|c| c.is_alphabetic())
+                    && word.chars().all(|c| c.is_alphanumeric() || c == '_');
+                if is_word {
+                    Some(word)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
 
-        let head = stmts[0].clone();
-        let body = self.collect_options(stmts[1..].to_vec().clone(), name.clone());
+    /// Makes the elements that follow a cut (`^`) into a single element. If any of
+    /// them fail, the error is made fatal, so no other options are tried and the
+    /// parse ends with the error where it actually happened.
+    fn make_cut(&mut self, rest: Vec<TokenStream>) -> TokenStream {
+        self.group_count += 1;
+        let ok = format_ident!("cut_ok_{}", self.group_count);
+        let chain = self.collect_options(rest, quote! { #ok = true; });
 
         quote! {
-            #head {
-                #body
+            let mut #ok = false;
+            #chain
+            if !#ok {
+                if let Some(Err(e)) = identifiers.last_mut() {
+                    e.Fatal = true;
+                }
             }
+            if #ok
         }
     }
 
-    /// Function used to auto-generate the parser function,
-    /// of the following format:
-    ///
-    /// Inputs: &mut TokenTracker
-    ///
-    /// Outputs: Result<AstOrToken, ()>
-    fn generate_parser(&self) -> TokenStream {
-        let top_name = &self.names[0];
+    /// Wraps an element so that it is matched as normal, but anything it added to
+    /// `identifiers` (and any labels inside of it) is thrown away.
+    fn make_dropped(&mut self, stmt: TokenStream) -> TokenStream {
+        self.group_count += 1;
+        let start = format_ident!("drop_start_{}", self.group_count);
+        let marks = format_ident!("drop_labels_{}", self.group_count);
+        let ok = format_ident!("drop_ok_{}", self.group_count);
+
         quote! {
-            pub fn parser(mut tracker: &mut TokenTracker) -> Result<AstOrToken, ParserError> {
-                //let mut res = vec![];
+            let #start = identifiers.len();
+            let #marks = labels.len();
+            let mut #ok = false;
+            #stmt {
+                #ok = true;
+            }
+            if #ok {
+                identifiers.truncate(#start);
+                labels.truncate(#marks);
+            }
+            if #ok
+        }
+    }
 
-                let mut tree = expect(&mut tracker, &#top_name);
-                tree
+    /// Wraps an element in a lookahead predicate. The element is run, and the
+    /// tracker and `identifiers` are always put back the way they were, so
+    /// nothing is consumed. `&e` succeeds if `e` matched; `!e` succeeds if it did not.
+    fn make_lookahead(&mut self, stmt: TokenStream, positive: bool) -> TokenStream {
+        self.group_count += 1;
+        let la_p = format_ident!("lookahead_pos_{}", self.group_count);
+        let la_s = format_ident!("lookahead_size_{}", self.group_count);
+        let la_ok = format_ident!("lookahead_ok_{}", self.group_count);
+        let la_err = format_ident!("lookahead_err_{}", self.group_count);
+        let la_state = format_ident!("lookahead_state_{}", self.group_count);
+        let checkpoint = self.make_checkpoint(&la_p, &la_s);
+        let backtrack = self.make_backtrack(&la_p, &la_s);
+
+        let (failed, message, success) = if positive {
+            (quote!(!#la_ok), "Expected lookahead to match", quote!(#la_ok))
+        } else {
+            (quote!(#la_ok), "Unexpected match in negative lookahead", quote!(!#la_ok))
+        };
+
+        // What a negative lookahead fails to match isn't expected there, so
+        // what the parse expected is put back the way it was afterwards.
+        let (save, restore) = if positive {
+            (quote!(), quote!())
+        } else {
+            (
+                quote!(let #la_state = PARSE_STATE.with(|state| { let state = state.borrow(); (state.farthest, state.farthest_used, state.expected.clone()) });),
+                quote!(PARSE_STATE.with(|state| { let mut state = state.borrow_mut(); state.farthest = #la_state.0; state.farthest_used = #la_state.1; state.expected = #la_state.2; });),
+            )
+        };
+
+        quote! {
+            #checkpoint
+            let mut #la_ok = false;
+            #save
+            #stmt {
+                #la_ok = true;
+            }
+            #restore
+            let #la_err: Vec<ParserError> = match identifiers.last() {
+                Some(Err(e)) if !#la_ok => vec![e.clone()],
+                _ => vec![],
+            };
+            #backtrack
+            if #failed {
+                identifiers.push(Err(ParserError::new(&mut tracker, #message.to_string(), #la_err)));
             }
+            if #success
         }
     }
 
-    /// Helper to put all the trait definitions in one location. This
-    /// trait will be implied for three different items:
+    /// Checks for the separated list operator after an item, and wraps the item
+    /// in a list if there is one:
     ///
-    /// - GrammarToken
-    /// - &str / maybe String
-    /// - (TokenType)
-    fn generate_expect_func(&self) -> TokenStream {
-        quote! {
-            pub fn expect(mut tracker: &mut TokenTracker, expected: &dyn Any) -> Result<AstOrToken, ParserError> {
-                //println!("----- In expect ------");
-                // For each type it could be, check if the token matches.
-                if let Some(grammar) = expected.downcast_ref::<GrammarToken>() { // Ast
-                    //println!("matching {:?}", grammar);
-                    //let ast = match_rule(&mut tracker, grammar);
-                    let ast = match_rule(&mut tracker, grammar);
-                    match ast {
-                        Ok(tree) => { return Ok(AstOrToken::Ast(tree)); },
-                        Err(m) => { return Err(m) }
+    /// - `item % ','`: Zero or more items, separated by commas.
+    /// - `item %+ ','`: One or more items, separated by commas.
+    ///
+    /// A trailing `?` after the separator (`item % ','?`) allows one extra separator
+    /// at the end of the list. Separators are matched but never added to the
+    /// tree, so the children only hold the items.
+    fn make_separated_list(&mut self, tr: &mut TokenTracker, item: TokenStream) -> Result<TokenStream, String> {
+        if peek_as_string(tr).ok().as_deref() != Some("%") {
+            return Ok(item);
+        }
+        let _percent = get_token(tr)?;
+        let at_least_one = self.take_modifier(tr, &["+"])?.is_some();
+
+        // The separator is a single item or token literal; modifiers don't make sense here.
+        let mut sep_tok = peek_token(tr)?;
+        let sep = if to_string(sep_tok.clone())? == "#" {
+            let _hash = get_token(tr)?;
+            sep_tok = peek_token(tr)?;
+            self.make_identifier_option(tr)?
+        } else {
+            self.make_single_if_statement(get_token(tr)?)?
+        };
+        let sep_name = format!("{}", quote!(#sep_tok));
+        let trailing = self.take_modifier(tr, &["?"])?.is_some();
+
+        self.group_count += 1;
+        let l_p = format_ident!("list_pos_{}", self.group_count);
+        let l_s = format_ident!("list_size_{}", self.group_count);
+        let count = format_ident!("list_count_{}", self.group_count);
+        let errs = format_ident!("list_errors_{}", self.group_count);
+        let checkpoint = self.make_checkpoint(&l_p, &l_s);
+        let backtrack = self.make_backtrack(&l_p, &l_s);
+
+        let trailing_sep = if trailing {
+            quote! {
+                if #count > 0 {
+                    #checkpoint
+                    let mut sep_ok = false;
+                    #sep {
+                        sep_ok = true;
                     }
-                    //return Some(AstOrToken::Ast(match_rule(&mut tracker, grammar)));
+                    if !sep_ok {
+                        #backtrack
+                    }
+                    identifiers.truncate(#l_s);
+                }
+            }
+        } else {
+            quote!()
+        };
+        let (too_few, success) = if at_least_one {
+            (quote!(#count == 0), quote!(#count > 0))
+        } else {
+            (quote!(false), quote!(true))
+        };
+        let message = format!("Expected one or more items separated by {}", sep_name);
+
+        Ok(quote! {
+            let mut #count: usize = 0;
+            let mut #errs: Vec<ParserError> = vec![];
+            loop {
+                #checkpoint
+                if #count > 0 {
+                    let mut sep_ok = false;
+                    #sep {
+                        sep_ok = true;
+                    }
+                    if !sep_ok {
+                        #backtrack
+                        break;
+                    }
+                    // Separators never make it into the tree.
+                    identifiers.truncate(#l_s);
+                }
+
+                let mut item_ok = false;
+                #item {
+                    item_ok = true;
+                }
+                if !item_ok {
+                    if let Some(Err(e)) = identifiers.last() {
+                        #errs = vec![e.clone()];
+                    }
+                    // This also gives back the separator eaten above.
+                    #backtrack
+                    break;
+                }
+                #count += 1;
+                if mark(&mut tracker) == #l_p {
+                    break;
+                }
+            }
+            #trailing_sep
+            if #too_few {
+                identifiers.push(Err(ParserError::new(&mut tracker, #message.to_string(), #errs)));
+            }
+            if #success
+        })
+    }
+
+    /// Checks for a recovery annotation at the end of a rule (`recover_to(';')`),
+    /// and takes it off of the rule if there is one. The tokens to recover to are
+    /// handed back as code that sets `found` if the tracker is sitting on one of
+    /// them, eating it if so.
+    fn take_recovery(&mut self, body: Vec<Token>) -> Result<(Vec<Token>, Option<TokenStream>), String> {
+        let i = match self.recovery_start(&body) {
+            Some(i) => i,
+            None => return Ok((body, None)),
+        };
+
+        // Each token to recover to is matched on its own, so nothing it pushes
+        // ends up in the rule's `identifiers`.
+        let mut tr = TokenTracker::new(&FlatStream::new_from_tokens(body[i + 2..body.len() - 1].to_vec()));
+        let mut sync = quote!();
+        while mark(&tr) < give_max(&tr) {
+            let head = if peek_as_string(&mut tr)? == "#" {
+                let _hash = get_token(&mut tr)?;
+                self.make_identifier_option(&mut tr)?
+            } else {
+                self.make_single_if_statement(get_token(&mut tr)?)?
+            };
+            sync = quote! {
+                #sync
+                if !found {
+                    let mut identifiers: Vec<Result<AstOrToken, ParserError>> = vec![];
+                    #head {
+                        found = true;
+                    }
+                }
+            };
+            if mark(&tr) < give_max(&tr) && get_as_string(&mut tr)? != "," {
+                return Err("The tokens given to `recover_to` must be separated by commas".to_string());
+            }
+        }
+        if sync.is_empty() {
+            return Err("`recover_to` needs at least one token to recover to".to_string());
+        }
+        Ok((body[..i].to_vec(), Some(sync)))
+    }
+
+    /// Finds where the `recover_to(...)` ending a rule's body starts, if it has one.
+    fn recovery_start(&self, body: &[Token]) -> Option<usize> {
+        let mut i = 0;
+        while i < body.len() {
+            if let Token::Begin(_, _) = body[i] {
+                i = self.group_end(body, i);
+                continue;
+            }
+            let is_recovery = matches!(&body[i], Token::Ident(ident) if ident == "recover_to")
+                && body.get(i + 1).is_some_and(|next| give_group_deliminator(next.clone()) == "(")
+                && self.group_end(body, i + 1) == body.len();
+            if is_recovery {
+                return Some(i);
+            }
+            i += 1;
+        }
+        None
+    }
+
+    /// Wraps a rule's body so that if it fails after getting at least one token
+    /// in, the tracker skips ahead from where it failed to just past the next
+    /// token to recover to (or the end of the input). The rule then gives back a
+    /// node holding only the error, so the rules using it can carry on.
+    ///
+    /// Errors from aborting actions are never recovered from.
+    fn make_recovery(&self, name: &Token, body: TokenStream, sync: TokenStream) -> TokenStream {
+        quote! {
+            let recover_start = mark(&mut tracker);
+            let result = (|| -> Result<AstNode, ParserError> { #body })();
+            match result {
+                Err(err) if err.User.is_none() && err.farthest() > recover_start => {
+                    let (farthest, farthest_used, expected) = PARSE_STATE.with(|state| {
+                        let mut state = state.borrow_mut();
+                        let stuck = (state.farthest, state.farthest_used, state.expected.clone());
+                        state.recoveries.push(stuck.clone());
+                        stuck
+                    });
+                    let err = expected_error(&mut tracker, farthest, &expected, err);
+                    reset(&mut tracker, err.farthest());
+                    loop {
+                        let mut found = false;
+                        #sync
+                        if found || get_token(&mut tracker).is_err() {
+                            break;
+                        }
+                    }
+                    // Looking for the sync tokens isn't part of the parse, so forget what it expected.
+                    PARSE_STATE.with(|state| {
+                        let mut state = state.borrow_mut();
+                        state.farthest = farthest;
+                        state.farthest_used = farthest_used;
+                        state.expected = expected;
+                    });
+                    Ok(AstNode::new(GrammarToken::#name, vec![Err(err)]))
+                }
+                result => result,
+            }
+        }
+    }
+
+    /// Checks for parameters after a rule's name (`list<X> := ...`), and eats
+    /// them if there are any.
+    fn take_params(&mut self) -> Result<Option<Vec<String>>, String> {
+        if peek_as_string(&mut self.tracker)? != "<" {
+            return Ok(None);
+        }
+        let _open = get_token(&mut self.tracker)?;
+
+        let mut params = vec![];
+        loop {
+            match get_token(&mut self.tracker)? {
+                Token::Ident(param) => params.push(param.to_string()),
+                other => return Err(format!("Expected a parameter name, found `{}`", to_string(other)?)),
+            }
+            match get_as_string(&mut self.tracker)?.as_str() {
+                "," => continue,
+                ">" => break,
+                other => return Err(format!("Expected `,` or `>` after a parameter, found `{}`", other)),
+            }
+        }
+        Ok(Some(params))
+    }
+
+    /// Replaces each use of a template in a rule (`list<expr>`) with the name of
+    /// the rule made for it (`list_expr`). The first use of a template with a
+    /// given set of arguments adds a rule for it, whose body is handed back to be
+    /// generated like any other. Action blocks and other brace groups are left alone.
+    fn expand_templates(&mut self, body: Vec<Token>) -> Result<(Vec<Token>, Vec<Vec<Token>>), String> {
+        let mut out = vec![];
+        let mut made = vec![];
+        let mut i = 0;
+        while i < body.len() {
+            let tok = body[i].clone();
+            if give_group_deliminator(tok.clone()) == "{" {
+                let end = self.group_end(&body, i);
+                out.extend_from_slice(&body[i..end]);
+                i = end;
+                continue;
+            }
+            if let Token::Ident(ident) = &tok {
+                if self.is_template_use(&body, i) {
+                    i += 1;
+                    let name = self.instantiate(&ident.to_string(), &body, &mut i, &mut made)?;
+                    out.push(Token::Ident(proc_macro2::Ident::new(&name, ident.span())));
+                    continue;
+                }
+            }
+            out.push(tok);
+            i += 1;
+        }
+        Ok((out, made))
+    }
+
+    /// Whether the token at `i` is the name of a template followed by its arguments.
+    fn is_template_use(&self, body: &[Token], i: usize) -> bool {
+        let is_template = match &body[i] {
+            Token::Ident(ident) => self.templates.iter().any(|t| ident == &t.name),
+            _ => false,
+        };
+        is_template && body.get(i + 1).is_some_and(|next| to_string(next.clone()).is_ok_and(|s| s == "<"))
+    }
+
+    /// Reads the arguments of a template's use, with `i` sitting on the `<`, and
+    /// gives back the name of the rule made for it. Arguments can be rule names,
+    /// or uses of templates themselves (`list<pair<key, value>>`).
+    fn instantiate(&mut self, template: &str, body: &[Token], i: &mut usize, made: &mut Vec<Vec<Token>>) -> Result<String, String> {
+        // Skip the '<'.
+        *i += 1;
+        let mut args = vec![];
+        loop {
+            let arg = match body.get(*i) {
+                Some(Token::Ident(arg)) if self.is_template_use(body, *i) => {
+                    *i += 1;
+                    self.instantiate(&arg.to_string(), body, i, made)?
+                }
+                Some(Token::Ident(arg)) => {
+                    *i += 1;
+                    arg.to_string()
+                }
+                _ => return Err(format!("Arguments given to `{}` must be rule names", template)),
+            };
+            args.push(arg);
+
+            let next = match body.get(*i) {
+                Some(next) => to_string(next.clone())?,
+                None => String::new(),
+            };
+            *i += 1;
+            match next.as_str() {
+                "," => continue,
+                ">" => break,
+                _ => return Err(format!("Expected `,` or `>` in the arguments given to `{}`", template)),
+            }
+        }
+
+        let found = self.templates.iter().find(|t| t.name == template).cloned().unwrap();
+        if found.params.len() != args.len() {
+            return Err(format!(
+                "`{}` takes {} argument(s), but was given {}",
+                template,
+                found.params.len(),
+                args.len()
+            ));
+        }
+
+        if let Some((_, _, name)) = self.instances.iter().find(|(t, a, _)| t == template && *a == args) {
+            return Ok(name.clone());
+        }
+
+        let name = format!("{}_{}", template, args.join("_"));
+        if self.is_rule(&name) {
+            return Err(format!(
+                "`{}<{}>` would be made into a rule named `{}`, but there is already a rule with that name",
+                template,
+                args.join(", "),
+                name
+            ));
+        }
+
+        // Swap each parameter for its argument, outside of brace groups.
+        let mut rule = vec![];
+        let mut j = 0;
+        while j < found.body.len() {
+            let tok = found.body[j].clone();
+            if give_group_deliminator(tok.clone()) == "{" {
+                let end = self.group_end(&found.body, j);
+                rule.extend_from_slice(&found.body[j..end]);
+                j = end;
+                continue;
+            }
+            match &tok {
+                Token::Ident(ident) if found.params.iter().any(|p| ident == p) => {
+                    let index = found.params.iter().position(|p| ident == p).unwrap();
+                    rule.push(Token::Ident(proc_macro2::Ident::new(&args[index], ident.span())));
+                }
+                _ => rule.push(tok),
+            }
+            j += 1;
+        }
+
+        self.names.push(Token::Ident(proc_macro2::Ident::new(&name, proc_macro2::Span::call_site())));
+        self.types.push(found.rule_type.clone());
+        if found.silent {
+            self.silent.push(name.clone());
+        }
+        self.instances.push((template.to_string(), args, name.clone()));
+        made.push(rule);
+        Ok(name)
+    }
+
+    /// Finds the index just past the end of the group starting at `start`.
+    fn group_end(&self, body: &[Token], start: usize) -> usize {
+        let mut depth = 0;
+        let mut i = start;
+        while i < body.len() {
+            match body[i] {
+                Token::Begin(_, _) => depth += 1,
+                Token::End(_, _) => depth -= 1,
+                _ => {}
+            }
+            i += 1;
+            if depth == 0 {
+                break;
+            }
+        }
+        i
+    }
+
+    /// Checks for a result type after a rule's name (`expr -> Expr := ...`), and
+    /// eats it if there is one.
+    fn take_rule_type(&mut self) -> Result<Option<TokenStream>, String> {
+        if peek_as_string(&mut self.tracker)? != "-" {
+            return Ok(None);
+        }
+        let _dash = get_token(&mut self.tracker)?;
+        if get_as_string(&mut self.tracker)? != ">" {
+            return Err("Expected `->` before the rule's type".to_string());
+        }
+
+        // The type runs up until the ':='.
+        let mut ty = quote!();
+        loop {
+            let pos = mark(&self.tracker);
+            let tok = get_token(&mut self.tracker)?;
+            if to_string(tok.clone())? == ":" && peek_as_string(&mut self.tracker)? == "=" {
+                reset(&mut self.tracker, pos);
+                break;
+            }
+            if let Token::Begin(_, _) = tok {
+                let mut tr = self.tracker.clone();
+                self.skip_group(&mut tr)?;
+                self.tracker = tr;
+            }
+            ty = quote!(#ty #tok);
+        }
+        Ok(Some(ty))
+    }
+
+    /// Looks up the result type given to a rule, if it has one.
+    fn rule_type(&self, rule: &str) -> Option<TokenStream> {
+        for (index, name) in self.names.iter().enumerate() {
+            if to_string(name.clone()).ok()? == rule {
+                return self.types[index].clone();
+            }
+        }
+        None
+    }
+
+    /// Checks for an action at the end of an option (`=> { ... }`, `=>? { ... }`
+    /// or `=>! { ... }`), and eats it if there is one. The block is handed back as written.
+    fn take_action(&self, tr: &mut TokenTracker) -> Result<Option<(TokenStream, ActionKind)>, String> {
+        let pos = mark(tr);
+        if get_as_string(tr)? == "=" && peek_as_string(tr).is_ok_and(|next| next == ">") {
+            let _arrow = get_token(tr)?;
+            let kind = match self.take_modifier(tr, &["?", "!"])?.as_deref() {
+                Some("?") => ActionKind::Fallible,
+                Some(_) => ActionKind::Aborting,
+                None => ActionKind::Value,
+            };
+            let block = get_token(tr)?;
+            if give_group_deliminator(block.clone()) != "{" {
+                return Err("An action needs a block, as in `=> { ... }`".to_string());
+            }
+            self.skip_group(tr)?;
+            return Ok(Some((quote!(#block), kind)));
+        }
+        reset(tr, pos);
+        Ok(None)
+    }
+
+    /// Generates the code that runs an action once its option has matched. Each
+    /// label in the option is bound to a variable of the same name, and the
+    /// result is stored as the node's value before the node is returned.
+    ///
+    /// A fallible action's `Err` is wrapped in a `ParserError` and left on top of
+    /// `identifiers`, so the option fails like any other. An aborting action's
+    /// error is fatal, so nothing backtracks past it.
+    fn make_action(&self, ty: &TokenStream, block: &TokenStream, kind: ActionKind, labels: &[(String, Shape)]) -> TokenStream {
+        let mut vars = vec![];
+        let mut values = vec![];
+        let mut bound: Vec<&str> = vec![];
+        for (label, shape) in labels {
+            // The same label can show up in more than one place (say, both sides of a group); the first one wins.
+            if bound.contains(&label.as_str()) {
+                continue;
+            }
+            bound.push(label);
+
+            vars.push(format_ident!("{}", label));
+            values.push(self.shape_value(shape, quote!(node.get_all(#label)), false));
+        }
+        // Every label is bound at once, so a label named `node` can't hide the node from the others.
+        let bindings = quote! {
+            #[allow(unused_variables, unused_parens)]
+            let (#(#vars,)*) = (#(#values,)*);
+        };
+
+        if kind == ActionKind::Value {
+            return quote! {
+                let value: #ty = {
+                    #bindings
+                    #block
+                };
+                node.value = Some(Rc::new(value));
+                return Ok(node);
+            };
+        }
+
+        let fatal = kind == ActionKind::Aborting;
+        quote! {
+            let value: Result<#ty, _> = {
+                #bindings
+                #block
+            };
+            match value {
+                Ok(value) => {
+                    node.value = Some(Rc::new(value));
+                    return Ok(node);
+                }
+                Err(error) => {
+                    // Point the error at the start of the option, not the end.
+                    reset(&mut tracker, pos);
+                    identifiers.push(Err(ParserError::user(&mut tracker, error, #fatal)));
+                }
+            }
+        }
+    }
+
+    /// Generates the expression that turns the children captured by a label
+    /// (a `Vec<&AstOrToken>`) into the value its shape binds to. In the typed
+    /// tree, rules without a type are built into their typed nodes rather than
+    /// handed back as an `AstNode`.
+    fn shape_value(&self, shape: &Shape, children: TokenStream, typed: bool) -> TokenStream {
+        let prefix = if typed { quote!(super::) } else { quote!() };
+        match shape {
+            Shape::Nothing => quote!(()),
+            Shape::Token | Shape::Rule(_) => self.single_value(shape, quote!(#children[0]), true, typed),
+            Shape::Picked { index, inner, .. } => self.single_value(inner, quote!(#children[#index]), true, typed),
+            Shape::Group { options, .. } if typed => {
+                let (matches, pattern, one) = self.group_value(shape, options);
+                quote! {{
+                    let #pattern = #matches.into_iter().next().unwrap();
+                    #one
+                }}
+            }
+            Shape::Optional(inner) => match &**inner {
+                Shape::Token | Shape::Rule(_) => {
+                    let one = self.single_value(inner, quote!(child), true, typed);
+                    quote!(#children.first().map(|child| #one))
+                }
+                Shape::Group { options, .. } if typed => {
+                    let (matches, pattern, one) = self.group_value(inner, options);
+                    quote!(#matches.into_iter().next().map(|#pattern| #one))
+                }
+                Shape::Picked { index, inner, .. } => {
+                    let one = self.single_value(inner, quote!(children[#index]), true, typed);
+                    quote! {{
+                        let children = #children;
+                        (!children.is_empty()).then(|| #one)
+                    }}
+                }
+                _ => {
+                    let all = self.shape_value(inner, quote!(children), typed);
+                    quote! {{
+                        let children = #children;
+                        (!children.is_empty()).then(|| #all)
+                    }}
+                }
+            },
+            Shape::Repeated(inner) => match &**inner {
+                Shape::Token | Shape::Rule(_) => {
+                    let one = self.single_value(inner, quote!(child), false, typed);
+                    quote!(#children.into_iter().map(|child| #one).collect::<::std::vec::Vec<_>>())
+                }
+                Shape::Picked { stride, index, inner, .. } => {
+                    let one = self.single_value(inner, quote!(chunk[#index]), false, typed);
+                    quote!(#children.chunks(#stride).map(|chunk| #one).collect::<::std::vec::Vec<_>>())
+                }
+                Shape::Group { options, .. } if typed => {
+                    let (matches, pattern, one) = self.group_value(inner, options);
+                    quote!(#matches.into_iter().map(|#pattern| #one).collect::<::std::vec::Vec<_>>())
+                }
+                _ => self.shape_value(&Shape::Children, children, typed),
+            },
+            Shape::Group { .. } | Shape::Children => {
+                quote!(#children.into_iter().cloned().collect::<::std::vec::Vec<#prefix AstOrToken>>())
+            }
+        }
+    }
+
+    /// The pieces of a group's value in the typed tree: the matches of the group
+    /// among `labels`, the pattern each match is bound to, and the value built from
+    /// one match. A group with a single field stands for that field.
+    fn group_value(&self, group: &Shape, options: &[GroupOption]) -> (TokenStream, TokenStream, TokenStream) {
+        let (path, name) = match group {
+            Shape::Group { path, name, .. } => (path, name),
+            _ => unreachable!(),
+        };
+        let tags: Vec<String> = (0..options.len()).map(|option| self.group_label(path, option)).collect();
+        let matches = quote!(node.group_matches(&[#(#tags),*], &labels));
+        match self.flattened(options) {
+            Some(field) => {
+                let label = &field.label;
+                let value = self.shape_value(&field.shape, quote!(node.get_among(#label, &labels)), true);
+                (matches, quote!((_, labels)), value)
+            }
+            None => {
+                let ty = format_ident!("{}", name);
+                (matches, quote!((option, labels)), quote!(#ty::from_match(node, option, &labels)))
+            }
+        }
+    }
+
+    /// The field a group stands for, if it has a single option holding a single field.
+    fn flattened<'a>(&self, options: &'a [GroupOption]) -> Option<&'a Field> {
+        match options {
+            [option] if option.fields.len() == 1 => option.fields.first(),
+            _ => None,
+        }
+    }
+
+    /// The value of a single child matched by a terminal or a rule. Typed nodes
+    /// are boxed when they stand alone, as rules can hold themselves.
+    fn single_value(&self, shape: &Shape, child: TokenStream, boxed: bool, typed: bool) -> TokenStream {
+        match shape {
+            Shape::Rule(rule) => match self.rule_type(rule) {
+                // Every option of a rule with a type builds a value of that type.
+                Some(ty) => quote!(#child.value::<#ty>().unwrap()),
+                None if typed => {
+                    let ty = format_ident!("{}", self.type_name(rule));
+                    let built = if boxed {
+                        quote!(::std::boxed::Box::new(#ty::from_node(node)))
+                    } else {
+                        quote!(#ty::from_node(node))
+                    };
+                    // A node recovered from holds only its error.
+                    let built = if self.recovering.contains(rule) {
+                        quote! {
+                            match node.error() {
+                                Some(err) => Err(err.clone()),
+                                None => Ok(#built),
+                            }
+                        }
+                    } else {
+                        built
+                    };
+                    quote! {
+                        match #child {
+                            super::AstOrToken::Ast(node) => #built,
+                            super::AstOrToken::Tok(tok) => panic!("Expected a rule, found token `{}`", tok.lexeme),
+                        }
+                    }
+                }
+                None => quote!(#child.node()),
+            },
+            _ => quote!(#child.token()),
+        }
+    }
+
+    /// The type a field of the given shape has in the typed tree. This mirrors
+    /// `shape_value`.
+    fn field_type(&self, shape: &Shape, boxed: bool) -> TokenStream {
+        match shape {
+            Shape::Nothing => quote!(()),
+            Shape::Token => quote!(super::Token),
+            Shape::Rule(rule) => match self.rule_type(rule) {
+                Some(ty) => ty,
+                None => {
+                    let ty = format_ident!("{}", self.type_name(rule));
+                    let ty = if boxed { quote!(::std::boxed::Box<#ty>) } else { quote!(#ty) };
+                    if self.recovering.contains(rule) {
+                        quote!(::std::result::Result<#ty, super::ParserError>)
+                    } else {
+                        ty
+                    }
+                }
+            },
+            Shape::Picked { inner, .. } => self.field_type(inner, boxed),
+            Shape::Optional(inner) => {
+                let inner = self.field_type(inner, true);
+                quote!(::std::option::Option<#inner>)
+            }
+            Shape::Repeated(inner) => match &**inner {
+                Shape::Token | Shape::Rule(_) | Shape::Picked { .. } | Shape::Group { .. } => {
+                    let inner = self.field_type(inner, false);
+                    quote!(::std::vec::Vec<#inner>)
+                }
+                _ => self.field_type(&Shape::Children, boxed),
+            },
+            Shape::Group { name, options, .. } => match self.flattened(options) {
+                Some(field) => self.field_type(&field.shape, boxed),
+                None => {
+                    let ty = format_ident!("{}", name);
+                    quote!(#ty)
+                }
+            },
+            Shape::Children => quote!(::std::vec::Vec<super::AstOrToken>),
+        }
+    }
+
+    /// The name of a rule's type in the typed tree: `comma_list` becomes `CommaList`.
+    fn type_name(&self, rule: &str) -> String {
+        rule.split('_')
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                let mut chars = part.chars();
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                    None => String::new(),
+                }
+            })
+            .collect()
+    }
+
+    /// Generates the typed tree's type for a rule: a struct if it has a single
+    /// option, or an enum with a variant per option if it has more. Each one can
+    /// be built from the rule's `AstNode` with `from_node`. The types of the groups
+    /// inside of the rule come along with it.
+    fn make_typed(&mut self, rule: &str, options: &[RuleOption]) -> TokenStream {
+        let name = self.type_name(rule);
+        let ty = format_ident!("{}", name);
+        let doc = format!(" Built from the `{}` rule.", rule);
+
+        let mut options = options.to_vec();
+        for option in options.iter_mut() {
+            self.name_groups(&name, &mut option.fields);
+        }
+        let groups: Vec<TokenStream> = options
+            .iter()
+            .flat_map(|option| self.group_types(rule, &option.fields))
+            .collect();
+
+        if options.len() == 1 {
+            let (defs, values) = self.typed_fields(&options[0].fields);
+            return quote! {
+                #[doc = #doc]
+                #[derive(Debug, Clone)]
+                pub struct #ty {
+                    #(pub #defs,)*
+                }
+
+                impl #ty {
+                    pub fn from_node(node: &super::AstNode) -> #ty {
+                        let labels = &node.labels[..];
+                        #ty {
+                            #(#values,)*
+                        }
+                    }
+                }
+
+                #(#groups)*
+            };
+        }
+
+        let mut variants = vec![];
+        let mut arms = vec![];
+        let mut used: Vec<String> = vec![];
+        for (index, option) in options.iter().enumerate() {
+            let variant = self.variant_name(&option.keyword, &option.fields, index, &mut used);
+            let (defs, values) = self.typed_fields(&option.fields);
+            variants.push(quote!(#variant { #(#defs,)* }));
+            arms.push(quote!(#index => #ty::#variant { #(#values,)* },));
+        }
+
+        quote! {
+            #[doc = #doc]
+            #[derive(Debug, Clone)]
+            pub enum #ty {
+                #(#variants,)*
+            }
+
+            impl #ty {
+                pub fn from_node(node: &super::AstNode) -> #ty {
+                    let labels = &node.labels[..];
+                    match node.option {
+                        #(#arms)*
+                        _ => unreachable!(),
+                    }
+                }
+            }
+
+            #(#groups)*
+        }
+    }
+
+    /// Names the variant for an option after its keyword or first field, falling
+    /// back to its position. `Self` can't name a variant, so it becomes `Self_`.
+    fn variant_name(&self, keyword: &Option<String>, fields: &[Field], index: usize, used: &mut Vec<String>) -> proc_macro2::Ident {
+        let mut name = match (keyword, fields.first()) {
+            (Some(keyword), _) => self.type_name(keyword),
+            (None, Some(field)) => self.type_name(&field.name),
+            (None, None) => format!("Alt{}", index),
+        };
+        if name == "Self" {
+            name.push('_');
+        }
+        if used.contains(&name) {
+            name = format!("{}{}", name, index);
+        }
+        used.push(name.clone());
+        format_ident!("{}", name)
+    }
+
+    /// Names the types of the groups held by the fields, and the groups inside of
+    /// them, after the type holding them and the field: `args` in `call` becomes
+    /// `CallArgs`. Names already taken by a rule or another group are numbered.
+    fn name_groups(&mut self, owner: &str, fields: &mut [Field]) {
+        for field in fields.iter_mut() {
+            let base = format!("{}{}", owner, self.type_name(&field.name));
+            if let Some(Shape::Group { name, options, .. }) = field.shape.group_mut() {
+                // A group standing for its field has no type, so its groups are named as if it weren't there.
+                if self.flattened(options).is_some() {
+                    self.name_groups(owner, &mut options[0].fields);
+                    continue;
+                }
+                let mut count = 1;
+                *name = base.clone();
+                while self.group_types.contains(name)
+                    || self.names.iter().any(|rule| to_string(rule.clone()).is_ok_and(|rule| self.type_name(&rule) == *name))
+                {
+                    count += 1;
+                    *name = format!("{}{}", base, count);
+                }
+                self.group_types.push(name.clone());
+                let owner = name.clone();
+                for option in options.iter_mut() {
+                    self.name_groups(&owner, &mut option.fields);
+                }
+            }
+        }
+    }
+
+    /// Generates the types of the groups held by the fields, and the groups inside
+    /// of them. Like a rule's type, each one is a struct if the group has a single
+    /// option, or an enum if it has more, and is built from one match of the group
+    /// with `from_match`. Groups standing for a single field have no type.
+    fn group_types(&self, rule: &str, fields: &[Field]) -> Vec<TokenStream> {
+        let mut types = vec![];
+        for field in fields {
+            let (name, options) = match field.shape.group() {
+                Some(Shape::Group { name, options, .. }) => (name, options),
+                _ => continue,
+            };
+            for option in options {
+                types.extend(self.group_types(rule, &option.fields));
+            }
+            if self.flattened(options).is_some() {
+                continue;
+            }
+
+            let ty = format_ident!("{}", name);
+            let doc = format!(" Built from a group in the `{}` rule.", rule);
+            if options.len() == 1 {
+                let (defs, values) = self.typed_fields(&options[0].fields);
+                types.push(quote! {
+                    #[doc = #doc]
+                    #[derive(Debug, Clone)]
+                    pub struct #ty {
+                        #(pub #defs,)*
+                    }
+
+                    impl #ty {
+                        #[allow(unused_variables)]
+                        fn from_match(node: &super::AstNode, option: usize, labels: &[(&'static str, usize, usize)]) -> #ty {
+                            #ty {
+                                #(#values,)*
+                            }
+                        }
+                    }
+                });
+                continue;
+            }
+
+            let mut variants = vec![];
+            let mut arms = vec![];
+            let mut used: Vec<String> = vec![];
+            for (index, option) in options.iter().enumerate() {
+                let variant = self.variant_name(&option.keyword, &option.fields, index, &mut used);
+                let (defs, values) = self.typed_fields(&option.fields);
+                variants.push(quote!(#variant { #(#defs,)* }));
+                arms.push(quote!(#index => #ty::#variant { #(#values,)* },));
+            }
+            types.push(quote! {
+                #[doc = #doc]
+                #[derive(Debug, Clone)]
+                pub enum #ty {
+                    #(#variants,)*
+                }
+
+                impl #ty {
+                    #[allow(unused_variables)]
+                    fn from_match(node: &super::AstNode, option: usize, labels: &[(&'static str, usize, usize)]) -> #ty {
+                        match option {
+                            #(#arms)*
+                            _ => unreachable!(),
+                        }
+                    }
+                }
+            });
+        }
+        types
+    }
+
+    /// Generates the definitions (`name: Type`) and initialisers (`name: value`)
+    /// for the fields of an option, read from the `labels` in scope. Fields sharing
+    /// a name are numbered.
+    fn typed_fields(&self, fields: &[Field]) -> (Vec<TokenStream>, Vec<TokenStream>) {
+        let mut defs = vec![];
+        let mut values = vec![];
+        let mut seen_labels: Vec<&str> = vec![];
+        let mut names: Vec<String> = vec![];
+        for field in fields {
+            if seen_labels.contains(&field.label.as_str()) {
+                continue;
+            }
+            seen_labels.push(&field.label);
+
+            let mut name = field.name.clone();
+            let mut count = 1;
+            while names.contains(&name) {
+                count += 1;
+                name = format!("{}_{}", field.name, count);
+            }
+            names.push(name.clone());
+
+            let ident = format_ident!("{}", name);
+            let label = &field.label;
+            let ty = self.field_type(&field.shape, true);
+            let value = self.shape_value(&field.shape, quote!(node.get_among(#label, &labels)), true);
+            defs.push(quote!(#ident: #ty));
+            values.push(quote!(#ident: #value));
+        }
+        (defs, values)
+    }
+
+    /// This function is a wrapper for options that arrive with in groups of parenthesis.
+    /// This allows us to add modifiers as we see fit, or even create matching
+    /// subgroups.
+    ///
+    /// Basically, its more complexity and I'm not sure if I know what to do here.
+    fn make_paren_group_option(
+        &mut self,
+        group: Token,
+        internals: Vec<Token>,
+        modifier: &str,
+        name: Token,
+    ) -> Result<Vec<TokenStream>, String> {
+        // Im really not sure how to do this. . .
+
+        // Make a new token stream out of the internals.
+        let mut tr = TokenTracker::new(&FlatStream::new_from_tokens(internals.clone()));
+
+        // The internals are options just like a rule's, so hand them back over to
+        // rule_gen_interior; this way everything that works in a rule (token literals,
+        // nested groups, modifiers) works the same at any depth.
+        let mut all_options = vec![];
+        let label_mark = self.labels_seen.len();
+        let path = self.group_paths.last().cloned().flatten();
+        self.nesting += 1;
+        let group_options = self.rule_gen_interior(&mut tr, name);
+        self.nesting -= 1;
+        let group_options = group_options?;
+
+        // Square brackets are just a fancy way of spelling '?'.
+        let mut modifier = modifier;
+        if modifier.is_empty() && give_group_deliminator(group) == "[" {
+            modifier = "?";
+        }
+
+        // A label missing from any of the group's options might not be there at all.
+        for index in label_mark..self.labels_seen.len() {
+            let label = self.labels_seen[index].0.clone();
+            if !group_options.iter().all(|option| option.labels.iter().any(|(l, _)| *l == label)) {
+                self.labels_seen[index].1.wrap(false);
+            }
+        }
+        match modifier {
+            "" => {}
+            "?" => self.wrap_labels(label_mark, false),
+            _ => self.wrap_labels(label_mark, true),
+        }
+
+        for (index, option) in group_options.into_iter().enumerate() {
+            if option.action.is_some() {
+                return Err("Actions (`=> { ... }`) can only be used at the end of a rule's option".to_string());
+            }
+            // Groups with a type of their own record which option matched, and where,
+            // so the typed tree can tell each match apart.
+            let stmts = match &path {
+                Some(path) => {
+                    self.group_count += 1;
+                    let ok = format_ident!("option_ok_{}", self.group_count);
+                    let chain = self.collect_options(option.stmts, quote! { #ok = true; });
+                    let stmt = quote! {
+                        let mut #ok = false;
+                        #chain
+                        if #ok
+                    };
+                    let label = self.group_label(path, index);
+                    vec![self.make_labelled(stmt, &label)]
+                }
+                None => option.stmts,
+            };
+            all_options.push(stmts);
+        }
+
+        Ok(vec![self.make_modified_group(all_options, modifier)?])
+    }
+
+    /// Takes the options of a group (each one a list of if statements, as made
+    /// by `make_single_if_statement`) and wraps them in the code needed to
+    /// apply the modifier:
+    ///
+    /// - `*`: Match zero or more times.
+    /// - `+`: Match one or more times.
+    /// - `?`: Match zero or one time.
+    /// - `{n}`, `{n,}`, `{n,m}`: Match exactly n, at least n, or n to m times. A group
+    ///   that could match again once it hits its upper bound fails, rather than leaving
+    ///   the extra matches to whatever comes after it.
+    ///
+    /// Anything else is treated as a group that has to match exactly once.
+    ///
+    /// The generated code ends in an open `if`, so the result can be nested
+    /// by `collect_options` the same way a single if statement would be. Every
+    /// successful repetition leaves its children in `identifiers`; a failed
+    /// group leaves a single `Err` on the top of `identifiers` instead.
+    fn make_modified_group(
+        &mut self,
+        options: Vec<Vec<TokenStream>>,
+        modifier: &str,
+    ) -> Result<TokenStream, String> {
+        // Each group gets its own number so nested groups never share markers.
+        self.group_count += 1;
+        let fb_p = format_ident!("fallback_pos_{}", self.group_count);
+        let fb_s = format_ident!("fallback_size_{}", self.group_count);
+        let got = format_ident!("get_got_{}", self.group_count);
+        let errs = format_ident!("group_errors_{}", self.group_count);
+        let count = format_ident!("group_count_{}", self.group_count);
+
+        // One pass over the group: try each option in turn, backing up after each failure.
+        let fb_checkpoint = self.make_checkpoint(&fb_p, &fb_s);
+        let backtrack = self.make_backtrack(&fb_p, &fb_s);
+        let mut attempt = quote! {
+            #fb_checkpoint
+            let mut #got = false;
+            let mut #errs: Vec<ParserError> = vec![];
+        };
+        for option in options {
+            let chain = self.collect_options(option, quote! { #got = true; });
+            attempt = quote! {
+                #attempt
+                if !#got {
+                    #chain
+                    if !#got {
+                        if let Some(Err(e)) = identifiers.last() {
+                            #errs.push(e.clone());
+                        }
+                        #backtrack
+                    }
+                }
+            };
+        }
+
+        let (min, max) = match modifier {
+            "?" => {
+                return Ok(quote! {
+                    #attempt
+                    if true
+                })
+            }
+            "*" => (0, None),
+            "+" => (1, None),
+            m if m.starts_with('{') => self.parse_bounds(m)?,
+            _ => {
+                return Ok(quote! {
+                    #attempt
+                    if !#got {
+                        identifiers.push(Err(ParserError::new(
+                            &mut tracker,
+                            "Failed to match group".to_string(),
+                            #errs,
+                        )));
+                    }
+                    if #got
+                })
+            }
+        };
+
+        // Repeat the group until it fails, or the upper bound is hit.
+        let start_p = format_ident!("start_pos_{}", self.group_count);
+        let start_s = format_ident!("start_size_{}", self.group_count);
+        let last_errs = format_ident!("last_errors_{}", self.group_count);
+        let stop = format_ident!("stop_pos_{}", self.group_count);
+        let saved = format_ident!("saved_state_{}", self.group_count);
+        let start_checkpoint = self.make_checkpoint(&start_p, &start_s);
+        let undo = self.make_backtrack(&start_p, &start_s);
+        let expected = match max {
+            None => format!("at least {}", min),
+            Some(m) if m == min => format!("exactly {}", min),
+            Some(m) => format!("between {} and {}", min, m),
+        };
+        let (at_max, in_range) = match max {
+            Some(m) => (quote! { #count == #m }, quote! { (#min..=#m).contains(&#count) }),
+            None => (quote! { false }, quote! { #count >= #min }),
+        };
+        Ok(quote! {
+            #start_checkpoint
+            let mut #count: usize = 0;
+            let mut #last_errs: Vec<ParserError> = vec![];
+            while !(#at_max) {
+                #attempt
+                if !#got {
+                    #last_errs = #errs;
+                    break;
+                }
+                #count += 1;
+                // A match that ate nothing would match forever, so it counts for every repetition left.
+                if mark(&mut tracker) == #fb_p {
+                    #count = #count.max(#min);
+                    break;
+                }
+            }
+            // Once the upper bound is hit, any further match is one too many, so
+            // count them all for the error.
+            let #stop = mark(&mut tracker);
+            if #at_max {
+                // Looking for more matches isn't part of the parse, so forget what it expected.
+                let #saved = PARSE_STATE.with(|state| { let state = state.borrow(); (state.farthest, state.farthest_used, state.expected.clone()) });
+                loop {
+                    #attempt
+                    if #got && mark(&mut tracker) != #fb_p {
+                        #count += 1;
+                        continue;
+                    }
+                    if #got {
+                        #backtrack
+                    }
+                    break;
+                }
+                PARSE_STATE.with(|state| { let mut state = state.borrow_mut(); state.farthest = #saved.0; state.farthest_used = #saved.1; state.expected = #saved.2; });
+            }
+            if !(#in_range) {
+                let message = format!("Expected {} matches of group, found {}", #expected, #count);
+                // The count is what went wrong, rather than whatever came after the group.
+                note_error(#stop, &message);
+                #undo
+                identifiers.push(Err(ParserError::new(&mut tracker, message, #last_errs)));
+            }
+            if #in_range
+        })
+    }
+
+    /// Reads the bounds out of a brace modifier, which is one of `{n}`, `{n,}`
+    /// or `{n,m}`. An upper bound of `None` means there is no upper bound.
+    fn parse_bounds(&self, modifier: &str) -> Result<(usize, Option<usize>), String> {
+        let inner = modifier.trim_start_matches('{').trim_end_matches('}');
+        let bad = || format!("Malformed repetition bounds `{}`", modifier);
+
+        let mut parts = inner.splitn(2, ',');
+        let min = parts.next().unwrap_or("").parse::<usize>().map_err(|_| bad())?;
+        let max = match parts.next() {
+            None => Some(min),
+            Some("") => None,
+            Some(m) => Some(m.parse::<usize>().map_err(|_| bad())?),
+        };
+
+        if let Some(m) = max {
+            if m < min || m == 0 {
+                return Err(bad());
+            }
+        }
+        Ok((min, max))
+    }
+
+    /// Looks at the token following a group or item, and eats it if it is one
+    /// of the given modifiers. A `"{"` in the list allows brace bounds.
+    fn take_modifier(&self, tr: &mut TokenTracker, modifiers: &[&str]) -> Result<Option<String>, String> {
+        // Check that there is actually a token there. If not, don't walk off the end and cause a panic.
+        if let Ok(next) = peek_token(tr) {
+            // Brace groups are bounded repetition, so eat the whole group and hand it back as a string.
+            if let Token::Begin(group, _) = next.clone() {
+                if give_group_deliminator(next) != "{" || !modifiers.contains(&"{") {
+                    return Ok(None);
+                }
+                let _begin = get_token(tr)?;
+                self.skip_group(tr)?;
+
+                let bounds: String = group.stream().to_string().split_whitespace().collect();
+                let modifier = format!("{{{}}}", bounds);
+                self.parse_bounds(&modifier)?;
+                return Ok(Some(modifier));
+            }
+
+            let next = to_string(next)?;
+            if modifiers.contains(&next.as_str()) {
+                let _null = get_token(tr)?;
+                return Ok(Some(next));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Applies a trailing modifier (if any) to a single item, such as `ident?` or
+    /// `#(TokenType::Comma)*`. The item is treated as a group with one option
+    /// holding one statement.
+    fn make_modified_item(&mut self, tr: &mut TokenTracker, stmt: TokenStream) -> Result<TokenStream, String> {
+        match self.take_modifier(tr, &["*", "+", "?", "{"])? {
+            Some(modifier) => self.make_modified_group(vec![vec![stmt]], &modifier),
+            None => Ok(stmt),
+        }
+    }
+
+    /// Generates the code marking a point to back up to with `make_backtrack`:
+    /// the tracker's position, how many identifiers and labels there are, and how
+    /// many insertions `parse_repaired` has used up.
+    fn make_checkpoint(&self, pos: &proc_macro2::Ident, size: &proc_macro2::Ident) -> TokenStream {
+        let used = format_ident!("{}_used", pos);
+        let marks = format_ident!("{}_labels", pos);
+        quote! {
+            let #pos = mark(&mut tracker);
+            let #size = identifiers.len();
+            let #marks = labels.len();
+            let #used = insertions_used();
+        }
+    }
+
+    /// Generates the code used to undo a failed attempt: every identifier and
+    /// label pushed since the checkpoint is dropped, and the tracker is moved back
+    /// to `pos`, giving back any insertions used since.
+    fn make_backtrack(&self, pos: &proc_macro2::Ident, size: &proc_macro2::Ident) -> TokenStream {
+        let abort = self.make_abort_check();
+        let used = format_ident!("{}_used", pos);
+        let marks = format_ident!("{}_labels", pos);
+        quote! {
+            #abort
+            identifiers.truncate(#size);
+            labels.truncate(#marks);
+            reset(&mut tracker, #pos);
+            give_back_insertions(#used);
+        }
+    }
+
+    /// Generates the check that stops a rule from backtracking past a fatal error,
+    /// handing it straight back to the caller instead.
+    fn make_abort_check(&self) -> TokenStream {
+        quote! {
+            if let Some(Err(e)) = identifiers.last() {
+                if e.Fatal {
+                    return Err(e.clone());
+                }
+            }
+        }
+    }
+
+    /// Making an identifier option is similar, but different.
+    /// For starters, an identifier matches a tokens *.identifier field through
+    /// its given type. This means that we need to grab a group of tokens, which
+    /// while more convenient, means we have to change how we proceed.
+    fn make_identifier_option(&mut self, tracker: &mut TokenTracker) -> Result<TokenStream, String> {
+        // So for this to work, the contents inside of the parentheses are all going to be
+        // equivalent to whatever the Token.identifier field is. For us, that is ::TokenType.
+        // So if something is within the parens, it is matched and will be overloaded for later.
+
+        // We can do this easily by grabbing the entire group marked by begin, and
+        // walking the tracker past the end of it (nested groups and all).
+        let group = get_token(tracker)?;
+        self.skip_group(tracker)?;
+
+        let stream = match &group {
+            Token::Begin(g, _) => g.stream(),
+            _ => TokenStream::new(),
+        };
+
+        // Kinds that carry data, like #(TokenType::Number(_)), are patterns rather than values,
+        // so they get checked with matches! instead of ==.
+        if self.is_pattern(stream.clone()) {
+            let name = stream.to_string().replace(' ', "");
+            return Ok(quote! {
+                identifiers.push(expect_kind(
+                    &mut tracker,
+                    &|identifier: &TokenType| matches!(identifier, #stream),
+                    #name,
+                ));
+                if identifiers.last().cloned().unwrap().is_ok()
+            });
+        }
+
+        // A set of kinds, like #(TokenType::Plus | TokenType::Minus), gets checked in one go
+        // instead of being handed to expect one at a time.
+        let kinds = self.split_kinds(stream);
+        if kinds.len() < 2 {
+            return self.make_single_if_statement(group);
+        }
+
+        let names: Vec<String> = kinds
+            .iter()
+            .map(|kind| kind.to_string().replace(' ', ""))
+            .collect();
+        let name = names.join(" or ");
+        Ok(quote! {
+            identifiers.push(expect_kind(
+                &mut tracker,
+                &|identifier: &TokenType| #( identifier == &(#kinds) )||*,
+                #name,
+            ));
+            if identifiers.last().cloned().unwrap().is_ok()
+        })
+    }
+
+    /// Checks whether the inside of a `#()` token literal has to be treated as a pattern:
+    /// anything holding a group (`Number(_)`, `Point { .. }`) or a bare `_`.
+    fn is_pattern(&self, stream: TokenStream) -> bool {
+        stream.into_iter().any(|tree| match tree {
+            TokenTree::Group(_) => true,
+            TokenTree::Ident(i) => i == "_",
+            _ => false,
+        })
+    }
+
+    /// Splits the inside of a `#()` token literal on its top-level `|` symbols.
+    fn split_kinds(&self, stream: TokenStream) -> Vec<TokenStream> {
+        let mut kinds = vec![];
+        let mut current = TokenStream::new();
+        for tree in stream {
+            match &tree {
+                TokenTree::Punct(p) if p.as_char() == '|' => {
+                    kinds.push(current);
+                    current = TokenStream::new();
+                }
+                _ => current.extend(std::iter::once(tree)),
+            }
+        }
+        kinds.push(current);
+        kinds
+    }
+
+    /// Walks the tracker past the end of a group whose `BEGIN` was just eaten,
+    /// including any groups nested inside of it.
+    fn skip_group(&self, tracker: &mut TokenTracker) -> Result<(), String> {
+        let mut end_count = 1;
+        while end_count != 0 {
+            match get_as_string(tracker)?.as_str() {
+                "BEGIN" => end_count += 1,
+                "END" => end_count -= 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// A function used to make a single if statement. Indent is used to make a unique
+    /// identifier in the case of a grammar such as the following:
+    ///
+    /// ``` expr := term '-' term ```
+    ///
+    /// where the grammar could otherwise become ambiguous.
+    ///
+    /// Additionally, the identifier used is returned for later use with the AstNode.
+    fn make_single_if_statement(&mut self, tok: Token) -> Result<TokenStream, String> {
+        if let Token::Ident(rule) = &tok {
+            if self.silent.contains(&rule.to_string()) {
+                return Ok(self.make_spliced(&tok));
+            }
+        }
+        let ident = self.make_identifier_pusher(tok.clone())?;
+
+        Ok(quote! {
+            //identifiers.push(expect(&mut tracker, &#tok));
+            #ident
+            //if identifiers.last().cloned().unwrap().is_some() // What the fuck.
+            if identifiers.last().cloned().unwrap().is_ok() 
+        })
+    }
+
+    /// Whether a rule has the given name.
+    fn is_rule(&self, rule: &str) -> bool {
+        self.names.iter().any(|name| matches!(name, Token::Ident(ident) if ident == rule))
+    }
+
+    /// Matches a rule marked with `~`, and splices the children of its node into
+    /// `identifiers` in place of the node. Labels inside of it move along with them,
+    /// apart from the `#0`, `#1`... labels its typed node is built from, which
+    /// would be taken for the fields of the rule using it.
+    fn make_spliced(&mut self, tok: &Token) -> TokenStream {
+        self.group_count += 1;
+        let ok = format_ident!("splice_ok_{}", self.group_count);
+
+        quote! {
+            let #ok = match expect(&mut tracker, &GrammarToken::#tok) {
+                Ok(AstOrToken::Ast(node)) => {
+                    let base = identifiers.len();
+                    labels.extend(
+                        node.labels
+                            .iter()
+                            .filter(|label| !label.0.starts_with('#'))
+                            .map(|label| (label.0, label.1 + base, label.2 + base)),
+                    );
+                    identifiers.extend(node.child);
+                    true
                 }
-                if let Some(string_literal) = expected.downcast_ref::<&str>() { // Literal string of tokens to match
-                    // ex: rule := identifier "->" option;
-                    //println!("MATCHING STRING LITERAL {:?}", string_literal);
+                other => {
+                    let ok = other.is_ok();
+                    identifiers.push(other);
+                    ok
+                }
+            };
+            if #ok
+        }
+    }
 
-                    let test_pos = mark(&mut tracker);
-                    let test = get_token(&mut tracker);
+    /// Helper function used to generate just the identifier to be pushed
+    /// when given a token.
+    ///
+    /// The wildcard `.` is the odd one out, as it matches any token at all.
+    fn make_identifier_pusher(&mut self, tok: Token) -> Result<TokenStream, String> {
+        if let Token::Punct(p) = &tok {
+            if p.as_char() == '.' {
+                return Ok(quote! {
+                    identifiers.push(expect_any(&mut tracker));
+                });
+            }
+        }
+        if let Token::Literal(l) = &tok {
+            let expected = self.make_literal(l)?;
+            return Ok(quote! {
+                identifiers.push(expect(&mut tracker, #expected));
+            });
+        }
+        // Rules are named through `GrammarToken`, so a rule never clashes with a
+        // variable in the generated code.
+        if let Token::Ident(ident) = &tok {
+            if self.is_rule(&ident.to_string()) {
+                return Ok(quote! {
+                    identifiers.push(expect(&mut tracker, &GrammarToken::#ident));
+                });
+            }
+        }
+        Ok(quote! {
+            identifiers.push(expect(&mut tracker, &#tok));
+        })
+    }
 
-                    if test.is_err() { // Ensure that an error works correctly.
-                        //println!("Returned None (test.is_err())");
-                        reset(&mut tracker, test_pos);
-                        let err = ParserError::new(&mut tracker, "Incomplete Statement".to_string(), vec![]);
-                        return Err(err);
-                        //return Err(());
+    /// Turns a literal from the grammar into the value handed to `expect`. Every
+    /// literal is matched against the token's lexeme:
+    ///
+    /// - Strings and raw strings (`"=>"`, `r"\n"`) match as written.
+    /// - Strings with an `i` suffix (`"select"i`) match case-insensitively.
+    /// - Strings with a `g` suffix (`"0x*"g`) are lexeme patterns; see `check_glob`.
+    /// - Characters and byte characters (`'-'`, `b'-'`) match as a single character.
+    /// - Byte strings (`b"let"`) match as the text they hold.
+    /// - Integers and floats (`0`, `0x1F`, `1.5`) match as written.
+    ///
+    /// Anything else (C strings, unknown suffixes) is rejected here, rather than
+    /// falling through `expect` at parse time.
+    fn make_literal(&self, lit: &proc_macro2::Literal) -> Result<TokenStream, String> {
+        let text = lit.to_string();
+        let unsupported = |why: &str| Err(format!("Unsupported literal `{}` in grammar: {}", text, why));
+
+        // Split the suffix (if any) off of quoted literals.
+        let quoted = text.starts_with('"')
+            || text.starts_with('\'')
+            || text.starts_with("r\"")
+            || text.starts_with("r#")
+            || text.starts_with('b')
+            || text.starts_with('c');
+        let (body, suffix) = match text.rfind(['"', '\'', '#']) {
+            Some(end) if quoted => text.split_at(end + 1),
+            _ => text.split_at(self.number_end(&text)),
+        };
+        // Rust is happy to parse the literal back out once the suffix is gone.
+        let body_lit: TokenStream = body.parse().map_err(|_| format!("Malformed literal `{}`", text))?;
+
+        if body.starts_with('c') {
+            return unsupported("C strings can not match a lexeme");
+        }
+        if body.starts_with('"') || body.starts_with('r') {
+            return match suffix {
+                "" => Ok(quote!(&#body_lit)),
+                "i" => Ok(quote!(&CaseInsensitive(#body_lit))),
+                "g" => {
+                    self.check_glob(&self.string_value(body))
+                        .map_err(|why| format!("Malformed lexeme pattern `{}`: {}", text, why))?;
+                    Ok(quote!(&LexemePattern(#body_lit)))
+                }
+                _ => unsupported("the only suffixes allowed on strings are `i` and `g`"),
+            };
+        }
+        if !suffix.is_empty() {
+            return unsupported("suffixes are only allowed on strings");
+        }
+        if body.starts_with('\'') {
+            return Ok(quote!(&#body_lit));
+        }
+        if body.starts_with("b'") {
+            return Ok(quote!(&(#body_lit as char)));
+        }
+        if body.starts_with('b') {
+            // Lexemes are Strings, so anything that isn't valid UTF-8 could never match.
+            return match String::from_utf8(self.byte_string_value(body)) {
+                Ok(value) => {
+                    let value = proc_macro2::Literal::string(&value);
+                    Ok(quote!(&#value))
+                }
+                Err(_) => unsupported("byte strings have to be valid UTF-8"),
+            };
+        }
+        if body.starts_with(|c: char| c.is_ascii_digit()) {
+            let as_written = proc_macro2::Literal::string(body);
+            return Ok(quote!(&#as_written));
+        }
+        unsupported("unknown literal kind")
+    }
+
+    /// Finds where the digits of a number literal end, and its suffix (`u8` in `1u8`,
+    /// `f32` in `2.0f32`) starts. Hex digits and exponents are part of the number.
+    fn number_end(&self, text: &str) -> usize {
+        let hex = text.starts_with("0x");
+        let prefix = if hex || text.starts_with("0b") || text.starts_with("0o") { 2 } else { 0 };
+        text.char_indices()
+            .skip(prefix)
+            .find(|(_, c)| c.is_alphabetic() && !(if hex { c.is_ascii_hexdigit() } else { *c == 'e' || *c == 'E' }))
+            .map_or(text.len(), |(i, _)| i)
+    }
+
+    /// Gets the bytes held by a byte string literal (`b"let"`, `br"\n"`), with its
+    /// escapes worked out.
+    fn byte_string_value(&self, body: &str) -> Vec<u8> {
+        let body = &body[1..];
+        if body.starts_with('r') {
+            return self.string_value(body).into_bytes();
+        }
+
+        let inner = &body[1..body.len() - 1];
+        let mut out = vec![];
+        let mut chars = inner.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.extend_from_slice(c.to_string().as_bytes());
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push(b'\n'),
+                Some('t') => out.push(b'\t'),
+                Some('r') => out.push(b'\r'),
+                Some('0') => out.push(0),
+                Some('x') => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    out.push(u8::from_str_radix(&hex, 16).unwrap_or_default());
+                }
+                // A backslash ending a line skips the line break and the whitespace after it.
+                Some('\n') => {
+                    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                        chars.next();
                     }
+                }
+                Some(other) => out.extend_from_slice(other.to_string().as_bytes()),
+                None => {}
+            }
+        }
+        out
+    }
 
-                    let top = test.unwrap();
-                    let lit_str = string_literal.to_string();
+    /// Gets the text held by a (non-byte) string literal, as written in the grammar.
+    /// Only the common escapes are handled; that is plenty for lexeme patterns.
+    fn string_value(&self, body: &str) -> String {
+        if let Some(raw) = body.strip_prefix('r') {
+            // Only the `r`, the hashes and one quote on each side are taken off, as
+            // the text itself can start or end with quotes (`r#""x""#`).
+            let hashes = raw.len() - raw.trim_start_matches('#').len();
+            return raw[hashes + 1..raw.len() - hashes - 1].to_string();
+        }
 
-                    if top.lexeme == lit_str.clone() {
-                        //println!("Returned Some");
-                        return Ok(AstOrToken::Tok(top.clone()));
+        let inner = &body[1..body.len() - 1];
+        let mut out = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some('0') => out.push('\0'),
+                Some(other) => out.push(other),
+                None => {}
+            }
+        }
+        out
+    }
+
+    /// Checks that a lexeme pattern is well formed. Patterns are simple globs:
+    ///
+    /// - `*` matches any run of characters (including none).
+    /// - `?` matches any single character.
+    /// - `[abc]` and `[a-z]` match one character out of a class, and `[!a-z]` one character outside of it.
+    /// - `\` matches the next character literally.
+    fn check_glob(&self, pattern: &str) -> Result<(), String> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '\\' if i + 1 == chars.len() => return Err("nothing left to escape after `\\`".to_string()),
+                '\\' => i += 1,
+                '[' => {
+                    // The class needs at least one character, so a `]` right away is part of it.
+                    let mut start = i + 1;
+                    if chars.get(start) == Some(&'!') {
+                        start += 1;
                     }
-                    //println!("Returned none");
-                    reset(&mut tracker, test_pos);
-                    let err = ParserError::new(&mut tracker, lit_str.clone(), vec![]);
-                    return Err(err);
-                    //return Err(());
+                    match chars.iter().skip(start + 1).position(|c| *c == ']') {
+                        Some(end) => i = start + 1 + end,
+                        None => return Err("character class is missing its `]`".to_string()),
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+
+    /// A recursive function used to collect a vector of TokenStream's into
+    /// one big nested TokenStream. Similar to macro expansion, except for
+    /// recursive rather than iterative
+    ///
+    /// `base` is placed in the innermost block, and only runs if every statement matched.
+    fn collect_options(&mut self, stmts: Vec<TokenStream>, base: TokenStream) -> TokenStream {
+        // Base case:
+        if stmts.is_empty() {
+            return base;
+        }
+        // otherwise:
+
+        let head = stmts[0].clone();
+        let body = self.collect_options(stmts[1..].to_vec(), base);
+
+        quote! {
+            #head {
+                #body
+            }
+        }
+    }
 
+    /// Function used to auto-generate the parser function,
+    /// of the following format:
+    ///
+    /// Inputs: &mut TokenTracker
+    ///
+    /// Outputs: Result<AstOrToken, ()>
+    ///
+    /// If the first rule has a type, `parse_value` is generated as well, which
+    /// hands back the value built by the first rule's actions. If it doesn't,
+    /// `parse_typed` is generated instead, which hands back the typed tree.
+    fn generate_parser(&self) -> TokenStream {
+        let top_name = &self.names[0];
+        let parse_value = match &self.types[0] {
+            Some(ty) => quote! {
+                pub fn parse_value(mut tracker: &mut TokenTracker) -> Result<#ty, ParserError> {
+                    let tree = parser(&mut tracker)?;
+                    Ok(tree.value::<#ty>().unwrap())
                 }
-                if let Some(literal) = expected.downcast_ref::<char>() { // Token
-                    //println!("MATCHING LITERAL {:?}", literal);
-                    // For this one, we have to match the lexeme field of the token
-                    let test_pos = mark(&mut tracker);
-                    let test = get_token(&mut tracker);
+            },
+            None => {
+                let ty = format_ident!("{}", self.type_name(&to_string(top_name.clone()).unwrap_or_default()));
+                quote! {
+                    pub fn parse_typed(mut tracker: &mut TokenTracker) -> Result<typed::#ty, ParserError> {
+                        let node = parser(&mut tracker)?.node();
+                        // The first rule can only have recovered from an error if it uses `recover_to` itself.
+                        if let Some(err) = node.error() {
+                            return Err(err.clone());
+                        }
+                        Ok(typed::#ty::from_node(&node))
+                    }
+                }
+            }
+        };
+        quote! {
+            pub fn parser(mut tracker: &mut TokenTracker) -> Result<AstOrToken, ParserError> {
+                //let mut res = vec![];
 
-                    if test.is_err() { // Ensure that an error works correctly.
-                        //println!("Returned None (test.is_err())");
-                        reset(&mut tracker, test_pos);
-                        let err = ParserError::new(&mut tracker, "Incomplete Statement".to_string(), vec![]);
-                        return Err(err);
-                        //return Err(());
+                PARSE_STATE.with(|state| {
+                    let mut state = state.borrow_mut();
+                    state.farthest = 0;
+                    state.farthest_used = 0;
+                    state.expected.clear();
+                    state.recoveries.clear();
+                    state.used.clear();
+                });
+                let mut tree = expect(&mut tracker, &GrammarToken::#top_name);
+
+                // Point the error at the furthest the parse got, rather than where the
+                // first rule started. Errors from actions are kept as they are.
+                tree.map_err(|err| {
+                    if err.User.is_some() {
+                        return err;
                     }
+                    let (farthest, expected) = PARSE_STATE.with(|state| {
+                        let state = state.borrow();
+                        (state.farthest, state.expected.clone())
+                    });
+                    expected_error(&mut tracker, farthest, &expected, err)
+                })
+            }
 
-                    let top = test.unwrap();
-                    let lit_str = literal.to_string();
+            /// Wraps `cause` in an error describing what was expected at `farthest`
+            /// and what was found there instead, as in "expected `)` or `,`, found `;`
+            /// at line 3". If nothing was expected there, `cause` is given back as it is.
+            fn expected_error(mut tracker: &mut TokenTracker, farthest: usize, expected: &[Expected], cause: ParserError) -> ParserError {
+                let shown: Vec<&str> = expected.iter().filter(|e| !e.hidden && !e.complete).map(|e| e.what.as_str()).collect();
+                let complete = expected.iter().find(|e| e.complete && !e.hidden);
+                let wanted = match shown.split_last() {
+                    _ if complete.is_some() => String::new(),
+                    Some((last, [])) => last.to_string(),
+                    Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+                    None => return cause,
+                };
 
-                    if top.lexeme == lit_str.clone() {
-                        //println!("Returned Some");
-                        return Ok(AstOrToken::Tok(top.clone()));
+                let pos = mark(&mut tracker);
+                reset(&mut tracker, farthest);
+                let (found, line) = match get_token(&mut tracker) {
+                    Ok(tok) => (format!("`{}`", tok.lexeme), tok.line),
+                    Err(_) => {
+                        // Use the line of the last token at the end of input.
+                        reset(&mut tracker, farthest.saturating_sub(1));
+                        ("end of input".to_string(), get_token(&mut tracker).map_or(0, |tok| tok.line))
+                    }
+                };
+                reset(&mut tracker, farthest);
+
+                let message = match complete {
+                    // Errors that aren't about a missing terminal are given as they are.
+                    Some(e) => e.what.clone(),
+                    None => format!("expected {}, found {} at line {}", wanted, found, line),
+                };
+                let fatal = cause.Fatal;
+                let mut err = ParserError::new(&mut tracker, message, vec![cause]);
+                err.Line = line;
+                err.Fatal = fatal;
+                reset(&mut tracker, pos);
+                err
+            }
+
+            #parse_value
+
+            /// The most repairs `parse_repaired` will make before giving up.
+            pub const MAX_REPAIRS: usize = 10;
+
+            /// Parses the same way as `parser`, but tries to fix the input when the parse gets
+            /// stuck: either the parse fails, it stops short of a point it got to, or a rule had
+            /// to recover (`recover_to`). At the point it got stuck, inserting each of the
+            /// terminals expected there is tried, and so is removing the token there. Whichever
+            /// matches the most terminals is kept, and the parse is run again.
+            ///
+            /// Gives back the tree along with an error describing each repair that was made.
+            pub fn parse_repaired(mut tracker: &mut TokenTracker) -> Result<(AstOrToken, Vec<ParserError>), ParserError> {
+                let start = mark(&mut tracker);
+                let mut repairs: Vec<Repair> = vec![];
+                let best = repair(&mut tracker, start, &mut repairs, MAX_REPAIRS, false);
+                let fixes = describe_repairs(&mut tracker, &best.used_repairs(&repairs));
+                reset(&mut tracker, best.end);
+                best.result.map(|tree| (tree, fixes))
+            }
+
+            /// Parses the whole input, collecting every error found along the way instead of
+            /// stopping at the first: the repairs made by `parse_repaired`, the errors rules
+            /// recovered from (`recover_to`), and whatever the parse couldn't get past.
+            /// The errors are given back in the order they appear in the input, along with
+            /// the tree if the parse got one, which may hold nodes made by recovering.
+            ///
+            /// Unlike `parse_repaired`, rules that recover (`recover_to`) are left to do so
+            /// rather than repaired, and this keeps going past `MAX_REPAIRS` for as long as the
+            /// repairs get the parse further. Where no repair helps, the token the parse is
+            /// stuck on is skipped, and the parse carries on from there.
+            pub fn parse_all(mut tracker: &mut TokenTracker) -> (Option<AstOrToken>, Vec<ParserError>) {
+                let start = mark(&mut tracker);
+                let mut repairs: Vec<Repair> = vec![];
+                let mut skipped: Vec<Repair> = vec![];
+                let mut diagnostics = vec![];
+                let mut limit = MAX_REPAIRS;
+                let mut best = repair(&mut tracker, start, &mut repairs, limit, true);
+                let mut tree = best.result.as_ref().ok().cloned();
+                let mut last: Option<usize> = None;
+
+                while !best.done() {
+                    let (stuck, _, _) = best.stuck();
+                    if repairs.len() >= limit && last < Some(stuck) {
+                        // The repairs ran out while they were still getting somewhere.
+                        limit = repairs.len() + MAX_REPAIRS;
+                    } else if best.result.is_err() || best.short {
+                        // The token skipped is the one the parse got stuck on, or for a parse that
+                        // insertions ended early, the first one it left.
+                        let at = if best.result.is_ok() { best.end.max(best.farthest) } else { best.farthest };
+                        let skip = Repair { pos: at, insert: None };
+                        reset(&mut tracker, at);
+                        if repairs.contains(&skip) || get_token(&mut tracker).is_err() {
+                            break;
+                        }
+                        // Insertions here got the parse nowhere, so they make way for the skip.
+                        repairs.retain(|repair| repair.pos < at || repair.insert.is_none());
+                        // A run of tokens the parse can't use is reported once, where it starts.
+                        if skipped.last().map(|last| last.pos + 1) != Some(at) {
+                            let clean = try_repairs(&mut tracker, start, &repairs, true);
+                            let expected = if clean.farthest == at { clean.expected } else { vec![] };
+                            reset(&mut tracker, at);
+                            let err = ParserError::new(&mut tracker, "Parser failed to match rules".to_string(), vec![]);
+                            diagnostics.push(expected_error(&mut tracker, at, &expected, err));
+                        }
+                        skipped.push(skip.clone());
+                        repairs.push(skip);
+                    } else {
+                        break;
+                    }
+                    last = Some(stuck);
+                    best = repair(&mut tracker, start, &mut repairs, limit, true);
+                    if let Ok(found) = &best.result {
+                        tree = Some(found.clone());
                     }
-                    //println!("Returned none");
-                    reset(&mut tracker, test_pos);
-                    let err = ParserError::new(&mut tracker, lit_str.clone(), vec![]);
-                    return Err(err);
                 }
-                if let Some(tok_type) = expected.downcast_ref::<TokenType> () { // Token
-                    //println!("matching tok_type {:?}", tok_type);
-                    // If we get here, we expect the token.identifier to match the de-referenced type
-                    let test_pos = mark(&mut tracker);
-                    let test = get_token(&mut tracker);
 
-                    if test.is_err() {
-                        reset(&mut tracker, test_pos);
-                        let err = ParserError::new(&mut tracker, "Incomplete Statement".to_string(), vec![]);
-                        return Err(err);
+                // Skipped tokens were reported as errors already, rather than as repairs.
+                let made: Vec<Repair> = best.used_repairs(&repairs).into_iter().filter(|repair| !skipped.contains(repair)).collect();
+                diagnostics.extend(describe_repairs(&mut tracker, &made));
+                match &best.result {
+                    Ok(found) => {
+                        if let AstOrToken::Ast(node) = found {
+                            diagnostics.extend(node.errors().into_iter().cloned());
+                        }
+                        if best.farthest > best.end {
+                            // The parse finished, but left tokens it couldn't match.
+                            reset(&mut tracker, best.farthest);
+                            let err = ParserError::new(&mut tracker, "Parser failed to match rules".to_string(), vec![]);
+                            diagnostics.push(expected_error(&mut tracker, best.farthest, &best.expected, err));
+                        }
+                    }
+                    // The tree from the last attempt that got one is given back, if any did.
+                    Err(err) => diagnostics.push(err.clone()),
+                }
+                reset(&mut tracker, best.end);
+                diagnostics.sort_by_key(|err| err.farthest());
+                (tree, diagnostics)
+            }
+
+            /// Repairs the input as described for `parse_repaired`, adding to `repairs` until
+            /// the parse gets through, no repair gets it further, or there are `limit` of them.
+            /// For `parse_all`, `whole_input` is set: rules that recover are left to, rather
+            /// than counting as the parse getting stuck, and insertions can't end the parse
+            /// while there is input left. Gives back the best attempt at parsing it.
+            fn repair(mut tracker: &mut TokenTracker, start: usize, repairs: &mut Vec<Repair>, limit: usize, whole_input: bool) -> Attempt {
+                let mut best = try_repairs(&mut tracker, start, repairs, whole_input);
+
+                while !best.done() && repairs.len() < limit {
+                    let (stuck, _, expected) = best.stuck();
+                    let mut candidates: Vec<Repair> = expected
+                        .iter()
+                        .filter(|e| e.insertable)
+                        .map(|e| Repair { pos: stuck, insert: Some(e.what.clone()) })
+                        .collect();
+                    candidates.push(Repair { pos: stuck, insert: None });
+
+                    let mut chosen: Option<(Repair, Attempt)> = None;
+                    for candidate in candidates {
+                        // A terminal can be missing more than once in a row, but a token can only be removed once.
+                        if candidate.insert.is_none() && repairs.contains(&candidate) {
+                            continue;
+                        }
+                        let mut trial = repairs.clone();
+                        trial.push(candidate.clone());
+                        let attempt = try_repairs(&mut tracker, start, &trial, whole_input);
+                        if !attempt.beats(&best) {
+                            continue;
+                        }
+                        if chosen.as_ref().map_or(true, |(_, current)| attempt.beats(current)) {
+                            chosen = Some((candidate, attempt));
+                        }
+                    }
+                    match chosen {
+                        Some((repair, attempt)) => {
+                            repairs.push(repair);
+                            best = attempt;
+                        }
+                        None => break,
+                    }
+                }
+                PARSE_STATE.with(|state| state.borrow_mut().repairs.clear());
+                best
+            }
+
+            /// Gives back an error describing each repair where it was made, as in
+            /// "Inserted missing `;`".
+            fn describe_repairs(mut tracker: &mut TokenTracker, repairs: &[Repair]) -> Vec<ParserError> {
+                let mut fixes = vec![];
+                for repair in repairs {
+                    reset(&mut tracker, repair.pos);
+                    let message = match &repair.insert {
+                        Some(expected) => format!("Inserted missing {}", expected),
+                        None => match get_token(&mut tracker) {
+                            Ok(tok) => format!("Removed unexpected `{}`", tok.lexeme),
+                            Err(_) => "Removed unexpected token".to_string(),
+                        },
+                    };
+                    reset(&mut tracker, repair.pos);
+                    fixes.push(ParserError::new(&mut tracker, message, vec![]));
+                }
+                fixes
+            }
+
+            /// One run of the parser with a set of repairs, and where it got to.
+            struct Attempt {
+                result: Result<AstOrToken, ParserError>,
+                end: usize,
+                farthest: usize,
+                farthest_used: usize,
+                expected: Vec<Expected>,
+                recoveries: Vec<(usize, usize, Vec<Expected>)>,
+                // Where the tokens removed by the repairs were.
+                removed: Vec<usize>,
+                // Which of the repairs the parse made use of, by index.
+                used: Vec<usize>,
+                // Whether insertions ended the parse before the end of the input when it had to get there.
+                short: bool,
+                // How many times rules recovered, when they were left to.
+                recovered: usize,
+            }
+
+            impl Attempt {
+                /// Whether the parse got all the way through without getting stuck.
+                fn done(&self) -> bool {
+                    self.result.is_ok() && self.farthest <= self.end && self.recoveries.is_empty() && !self.short
+                }
+
+                /// Where the parse first got stuck, how many insertions it had used
+                /// getting there, and what it expected there.
+                fn stuck(&self) -> (usize, usize, Vec<Expected>) {
+                    match self.recoveries.first() {
+                        Some(recovery) => recovery.clone(),
+                        None => (self.farthest, self.farthest_used, self.expected.clone()),
                     }
-                    let top = test.unwrap();
+                }
 
+                /// How many terminals the parse matched before getting stuck: every token
+                /// up to there that wasn't removed, and every insertion used.
+                fn progress(&self) -> usize {
+                    let (pos, used, _) = self.stuck();
+                    pos + used - self.removed.iter().filter(|removed| **removed < pos).count()
+                }
 
-                    //println!("Token: {:?}", top.clone());
-                    //println!("Identifier: {:?}", tok_type);
+                /// The repairs out of `repairs` (the ones the attempt was made with) that the
+                /// parse made use of. Repairs that stopped mattering once later ones were made
+                /// are left out, as they never made it into the tree.
+                fn used_repairs(&self, repairs: &[Repair]) -> Vec<Repair> {
+                    self.used.iter().map(|index| repairs[*index].clone()).collect()
+                }
 
-                    let identifier = top.clone().identifier;
+                /// Whether this attempt got further than another. Getting through only counts
+                /// if it didn't stop short of the other, so input that parsed is never given up,
+                /// and between two that got through, the one that recovered less often wins.
+                fn beats(&self, other: &Attempt) -> bool {
+                    if self.done() || other.done() {
+                        return self.done()
+                            && (!other.done() || self.recovered < other.recovered)
+                            && self.end >= other.end;
+                    }
+                    let (_, _, expected) = self.stuck();
+                    let (_, _, other_expected) = other.stuck();
+                    self.progress() > other.progress()
+                        || (self.progress() == other.progress()
+                            && expected != other_expected
+                            && self.recoveries.len() <= other.recoveries.len())
+                }
+            }
 
-                    if &identifier == &tok_type.clone() {
-                        //println!("returned some");
-                        return Ok(AstOrToken::Tok(top));
+            fn try_repairs(mut tracker: &mut TokenTracker, start: usize, repairs: &[Repair], whole_input: bool) -> Attempt {
+                PARSE_STATE.with(|state| {
+                    *state.borrow_mut() = ParseState {
+                        repairs: repairs.to_vec(),
+                        ..ParseState::default()
+                    };
+                });
+                reset(&mut tracker, start);
+                let result = parser(&mut tracker);
+                let end = mark(&mut tracker);
+                let more = whole_input && get_token(&mut tracker).is_ok();
+                reset(&mut tracker, end);
+                PARSE_STATE.with(|state| {
+                    let state = state.borrow();
+                    // A parse that got through used the insertions it stood in for, and every
+                    // removal before where it ended. One that failed used whatever came before
+                    // where it got stuck.
+                    let used = (0..repairs.len())
+                        .filter(|index| match (&result, &repairs[*index].insert) {
+                            (Ok(_), Some(_)) => state.used.contains(index),
+                            (Ok(_), None) => repairs[*index].pos < end,
+                            (Err(_), _) => repairs[*index].pos <= state.farthest,
+                        })
+                        .collect::<Vec<usize>>();
+                    // Insertions at the end of a parse that had to get through the whole input
+                    // closed it off with tokens still to come.
+                    let short = more && used.iter().any(|index| repairs[*index].insert.is_some() && repairs[*index].pos >= end);
+                    Attempt {
+                        result,
+                        end,
+                        farthest: state.farthest,
+                        farthest_used: state.farthest_used,
+                        expected: state.expected.clone(),
+                        recoveries: if whole_input { vec![] } else { state.recoveries.clone() },
+                        recovered: if whole_input { state.recoveries.len() } else { 0 },
+                        removed: repairs.iter().filter(|repair| repair.insert.is_none()).map(|repair| repair.pos).collect(),
+                        used,
+                        short,
                     }
-                    else {
-                        //println!("returned none");
-                        reset(&mut tracker, test_pos);
-                        let err = ParserError::new(&mut tracker, format!("Failed to match {:?}", tok_type.clone()), vec![]); 
-                        return Err(err);
-                        //return Err(());
+                })
+            }
+        }
+    }
+
+    /// Helper to put all the trait definitions in one location. This
+    /// trait will be implied for three different items:
+    ///
+    /// - GrammarToken
+    /// - &str / maybe String
+    /// - CaseInsensitive (&str, ignoring case)
+    /// - LexemePattern (a glob over the lexeme)
+    /// - (TokenType)
+    ///
+    /// Also generates `expect_kind`, used for sets of token kinds, and
+    /// `expect_any`, used for the `.` wildcard.
+    fn generate_expect_func(&self) -> TokenStream {
+        quote! {
+            pub fn expect(mut tracker: &mut TokenTracker, expected: &dyn Any) -> Result<AstOrToken, ParserError> {
+                //println!("----- In expect ------");
+                // For each type it could be, check if the token matches.
+                if let Some(grammar) = expected.downcast_ref::<GrammarToken>() { // Ast
+                    //println!("matching {:?}", grammar);
+                    //let ast = match_rule(&mut tracker, grammar);
+                    let ast = match_rule(&mut tracker, grammar);
+                    match ast {
+                        Ok(tree) => { return Ok(AstOrToken::Ast(tree)); },
+                        Err(m) => { return Err(m) }
                     }
+                    //return Some(AstOrToken::Ast(match_rule(&mut tracker, grammar)));
+                }
+                if let Some(string_literal) = expected.downcast_ref::<&str>() { // Literal string of tokens to match
+                    // ex: rule := identifier "->" option;
+                    let lit_str = string_literal.to_string();
+                    return expect_token(
+                        &mut tracker,
+                        &|top| top.lexeme == lit_str,
+                        format!("`{}`", lit_str),
+                        lit_str.clone(),
+                        Some(&|tok| tok.lexeme = lit_str.clone()),
+                    );
+                }
+                if let Some(insensitive) = expected.downcast_ref::<CaseInsensitive>() { // Literal string, ignoring case
+                    // ex: rule := "select"i columns "from"i table;
+                    let lit_str = insensitive.0.to_string();
+                    return expect_token(
+                        &mut tracker,
+                        &|top| top.lexeme.to_lowercase() == lit_str.to_lowercase(),
+                        format!("`{}`", lit_str),
+                        lit_str.clone(),
+                        Some(&|tok| tok.lexeme = lit_str.clone()),
+                    );
+                }
+                if let Some(pattern) = expected.downcast_ref::<LexemePattern>() { // Glob checked against the lexeme
+                    // ex: rule := "@*"g | "0x[0-9a-f]*"g;
+                    let pat: Vec<char> = pattern.0.chars().collect();
+                    return expect_token(
+                        &mut tracker,
+                        &|top| glob_match(&pat, &top.lexeme.chars().collect::<Vec<char>>()),
+                        format!("a lexeme like `{}`", pattern.0),
+                        format!("Failed to match pattern {:?}", pattern.0),
+                        None,
+                    );
+                }
+                if let Some(literal) = expected.downcast_ref::<char>() { // Token
+                    // For this one, we have to match the lexeme field of the token
+                    let lit_str = literal.to_string();
+                    return expect_token(
+                        &mut tracker,
+                        &|top| top.lexeme == lit_str,
+                        format!("`{}`", lit_str),
+                        lit_str.clone(),
+                        Some(&|tok| tok.lexeme = lit_str.clone()),
+                    );
+                }
+                if let Some(tok_type) = expected.downcast_ref::<TokenType> () { // Token
+                    // If we get here, we expect the token.identifier to match the de-referenced type
+                    return expect_token(
+                        &mut tracker,
+                        &|top| &top.identifier == tok_type,
+                        format!("{:?}", tok_type),
+                        format!("Failed to match {:?}", tok_type),
+                        Some(&|tok| {
+                            tok.identifier = tok_type.clone();
+                            tok.lexeme = String::new();
+                        }),
+                    );
                 }
                 let err = ParserError::new(&mut tracker, "Unexpected token given to `expect`".to_string(), vec![]);
                 return Err(err);
             }
+
+            /// Matches a token whose identifier passes the given check. This is used for
+            /// sets of token kinds, like #(TokenType::Plus | TokenType::Minus), and for
+            /// patterns, like #(TokenType::Number(_)).
+            pub fn expect_kind(
+                mut tracker: &mut TokenTracker,
+                check: &dyn Fn(&TokenType) -> bool,
+                name: &str,
+            ) -> Result<AstOrToken, ParserError> {
+                expect_token(
+                    &mut tracker,
+                    &|top| check(&top.identifier),
+                    name.to_string(),
+                    format!("Failed to match {}", name),
+                    None,
+                )
+            }
+
+            /// Matches the next token against `check`. When it doesn't match, what
+            /// was expected is noted for error reporting and repairs, and the error
+            /// given back holds `message` (or "Incomplete Statement" at the end of input).
+            ///
+            /// If `parse_repaired` is trying to insert what was expected here, a
+            /// nearby token is changed by `fill` to stand in for it instead.
+            fn expect_token(
+                mut tracker: &mut TokenTracker,
+                check: &dyn Fn(&Token) -> bool,
+                expected: String,
+                message: String,
+                fill: Option<&dyn Fn(&mut Token)>,
+            ) -> Result<AstOrToken, ParserError> {
+                let test_pos = mark(&mut tracker);
+
+                // Step over any tokens `parse_repaired` is trying without.
+                let mut at = test_pos;
+                while PARSE_STATE.with(|state| {
+                    state.borrow().repairs.iter().any(|repair| repair.pos == at && repair.insert.is_none())
+                }) {
+                    if get_token(&mut tracker).is_err() {
+                        break;
+                    }
+                    at = mark(&mut tracker);
+                }
+                let test = get_token(&mut tracker);
+                if let Ok(top) = &test {
+                    if check(top) {
+                        return Ok(AstOrToken::Tok(top.clone()));
+                    }
+                }
+                reset(&mut tracker, at);
+
+                let insert = PARSE_STATE.with(|state| {
+                    let mut state = state.borrow_mut();
+                    let used = state.used.len();
+                    if at > state.farthest || state.expected.is_empty() {
+                        state.farthest = at;
+                        state.farthest_used = used;
+                        state.expected.clear();
+                    }
+                    if at == state.farthest {
+                        state.farthest_used = state.farthest_used.max(used);
+                        if !state.expected.iter().any(|e| e.what == expected) {
+                            state.expected.push(Expected { what: expected.clone(), insertable: fill.is_some(), hidden: false, complete: false });
+                        }
+                    }
+                    if fill.is_none() {
+                        return false;
+                    }
+                    // Use up the first insertion here that hasn't stood in for a terminal yet.
+                    let unused = (0..state.repairs.len()).find(|index| {
+                        let repair = &state.repairs[*index];
+                        repair.pos == at && repair.insert.as_ref() == Some(&expected) && !state.used.contains(index)
+                    });
+                    match unused {
+                        Some(index) => {
+                            state.used.push(index);
+                            true
+                        }
+                        None => false,
+                    }
+                });
+                if let (true, Some(fill)) = (insert, fill) {
+                    // Borrow the next token (or the last one, at the end of input) for the missing one.
+                    let near = get_token(&mut tracker).or_else(|_| {
+                        reset(&mut tracker, at.saturating_sub(1));
+                        get_token(&mut tracker)
+                    });
+                    reset(&mut tracker, at);
+                    if let Ok(mut tok) = near {
+                        fill(&mut tok);
+                        return Ok(AstOrToken::Tok(tok));
+                    }
+                    PARSE_STATE.with(|state| state.borrow_mut().used.pop());
+                }
+                reset(&mut tracker, test_pos);
+
+                let message = if test.is_err() { "Incomplete Statement".to_string() } else { message };
+                Err(ParserError::new(&mut tracker, message, vec![]))
+            }
+
+            /// Checks a lexeme against a glob from the grammar (`*`, `?`, `[a-z]`, `[!a-z]` and `\`).
+            fn glob_match(pattern: &[char], text: &[char]) -> bool {
+                match pattern.first() {
+                    None => text.is_empty(),
+                    Some('*') => (0..=text.len()).any(|i| glob_match(&pattern[1..], &text[i..])),
+                    Some('?') => !text.is_empty() && glob_match(&pattern[1..], &text[1..]),
+                    Some('\\') => text.first() == pattern.get(1) && glob_match(&pattern[2..], &text[1..]),
+                    Some('[') => {
+                        let negate = pattern.get(1) == Some(&'!');
+                        let start = if negate { 2 } else { 1 };
+                        // The grammar made sure the class is closed and not empty.
+                        let end = start + 1 + pattern[start + 1..].iter().position(|c| *c == ']').unwrap();
+                        let class = &pattern[start..end];
+
+                        let c = match text.first() {
+                            Some(c) => *c,
+                            None => return false,
+                        };
+                        let mut found = false;
+                        let mut i = 0;
+                        while i < class.len() {
+                            if i + 2 < class.len() && class[i + 1] == '-' {
+                                found |= class[i] <= c && c <= class[i + 2];
+                                i += 3;
+                            } else {
+                                found |= class[i] == c;
+                                i += 1;
+                            }
+                        }
+                        found != negate && glob_match(&pattern[end + 1..], &text[1..])
+                    }
+                    Some(c) => text.first() == Some(c) && glob_match(&pattern[1..], &text[1..]),
+                }
+            }
+
+            /// Matches the `.` wildcard: any single token, as long as there is one left.
+            pub fn expect_any(mut tracker: &mut TokenTracker) -> Result<AstOrToken, ParserError> {
+                expect_token(
+                    &mut tracker,
+                    &|_| true,
+                    "any token".to_string(),
+                    "Incomplete Statement".to_string(),
+                    None,
+                )
+            }
         }
     }
 
@@ -706,3 +3286,52 @@ impl Collector {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(text: &str) -> proc_macro2::Literal {
+        match text.parse::<TokenStream>().unwrap().into_iter().next() {
+            Some(TokenTree::Literal(lit)) => lit,
+            other => panic!("`{}` is not a literal: {:?}", text, other),
+        }
+    }
+
+    fn make_literal(text: &str) -> Result<String, String> {
+        let collector = Collector::new(TokenStream::new());
+        collector.make_literal(&literal(text)).map(|expected| expected.to_string())
+    }
+
+    #[test]
+    fn literals_that_can_never_match_are_rejected() {
+        let rejected = [
+            ("1u8", "suffixes are only allowed on strings"),
+            ("2.0f32", "suffixes are only allowed on strings"),
+            ("0x1Fu8", "suffixes are only allowed on strings"),
+            ("b\"\\x80\"", "byte strings have to be valid UTF-8"),
+            ("c\"let\"", "C strings can not match a lexeme"),
+            ("\"let\"q", "the only suffixes allowed on strings are `i` and `g`"),
+        ];
+        for (text, why) in rejected.iter() {
+            let err = make_literal(text).unwrap_err();
+            assert!(err.contains(why), "`{}` was rejected with: {}", text, err);
+        }
+    }
+
+    #[test]
+    fn literals_match_the_text_they_hold() {
+        assert_eq!(make_literal("0x1F").unwrap(), "& \"0x1F\"");
+        assert_eq!(make_literal("1e5").unwrap(), "& \"1e5\"");
+        assert_eq!(make_literal("b\"\\\\x80\"").unwrap(), "& \"\\\\x80\"");
+        assert_eq!(make_literal("b\"\\x41\\\n    B\"").unwrap(), "& \"AB\"");
+    }
+
+    #[test]
+    fn raw_strings_only_lose_their_delimiters() {
+        let collector = Collector::new(TokenStream::new());
+        assert_eq!(collector.string_value("r#\"\"x\"\"#"), "\"x\"");
+        assert_eq!(collector.string_value("r\"\\n\""), "\\n");
+        assert_eq!(collector.string_value("r##\"#\"##"), "#");
+    }
+}
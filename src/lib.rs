@@ -49,10 +49,66 @@
  * Basically you need a rule name, the ```:=```, and what the rule is, and a semicolon. The following symbols are supported:
  *
  *  - Rule_names: ```a```, ```b```, ```whatever_the_heck_you_want```
+ *    Each rule is a variant of the generated ```GrammarToken``` enum. The variants aren't imported where the macro is used,
+ *    so refer to a rule as ```GrammarToken::rule_name```. Rules can then share names with anything else, such as ```value``` or ```node```.
  *  - The or bar: The ```|``` symbol indicates a different option, like an or. Not having one leads to sequential matching of internals.
  *  - Groups: ```(Paren groups)```, ```[optional groups]```;
- *     - Additionally, groups can use the modifiers of: '```*```' => Match zero or more. (Just don't use the '*' with a [] -- weird shit happens.)
+ *     - Additionally, groups can use the modifiers of: '```*```' => Match zero or more, '```+```' => Match one or more,
+ *       and '```?```' => Match zero or one (the same as ```[optional groups]```).
+ *       Bounded repetition is written with braces: ```{n}``` => Exactly n, ```{n,}``` => At least n, ```{n,m}``` => Between n and m.
+ *       A group that matches too few or too many times fails with an error naming the range: ```Expected between 2 and 3 matches of group, found 4```.
+ *       Every repetition is added to the children of the rule's node.
+ *     - A group's option matches only when every element in it matches. Otherwise whatever it matched is given back,
+ *       and the next option is tried, so ```*```, ```+``` and ```[ ]``` never keep part of a repetition. A group without a modifier
+ *       that fails makes the option holding it fail, and the rule moves on to its next option.
+ *     - Single items (rule names, literals and ```#()``` token literals) accept the same modifiers without needing
+ *       a group around them. Ex: ```args := expr* '-'? #(TokenType::Comma)+;```
  *  - Token Literals: Because you can provide TokenTypes, you can match them literally through here. Ex: ```#(TokenType::FatArrow)```
+ *    Several kinds can share one literal, which matches a token of any of them: ```#(TokenType::Plus | TokenType::Minus)```
+ *    Kinds that carry data can be matched with a pattern instead, checked with ```matches!``` rather than ```==```:
+ *    ```#(TokenType::Number(_))```. Anything holding a group or a bare ```_``` is treated as a pattern.
+ *  - Other literals: numbers (```0```, ```0x1F```, ```1.5```), byte strings (```b"let"```), byte characters (```b'='```) and raw strings
+ *    (```r"\n"```) are all matched against the lexeme as written. Literals that can never match a lexeme are rejected when the macro expands.
+ *  - Case-insensitive strings: a string literal with an ```i``` suffix ignores case when matching the lexeme.
+ *    Ex: ```"select"i``` matches ```SELECT```, ```select``` and ```Select```.
+ *  - Lexeme patterns: a string literal with a ```g``` suffix is a glob checked against the lexeme. ```*``` matches any run of characters,
+ *    ```?``` any one character, ```[a-z]``` one character from a class, ```[!a-z]``` one character outside of it, and ```\``` escapes the next character.
+ *    Ex: ```"@*"g``` matches any lexeme starting with ```@```, and ```"0x[0-9a-fA-F]*"g``` matches hex numbers.
+ *  - Wildcard: ```.``` matches any single token, and only fails at the end of input. Ex: ```skip := (!';' .)* ';';```
+ *  - Separated lists: ```item % ','``` matches zero or more items separated by commas, and ```item %+ ','``` matches one or more.
+ *    Writing ```item % ','?``` also allows a trailing separator. Only the items are added to the tree, not the separators.
+ *  - Labels: any element can be given a name, as in ```stmt := "let" name:ident '=' value:expr;```. The node built for the rule
+ *    hands labelled children back with ```node.get("name")```, or ```node.get_all("name")``` for labelled groups and repetitions.
+ *  - Actions: a rule given a type (```expr -> Expr := ...```) ends each of its options with a block of Rust code that builds its value,
+ *    as in ```expr -> Expr := a:term '+' b:expr => { Expr::Add(Box::new(a), Box::new(b)) } | t:term => { t };```.
+ *    Inside the block, each label is a variable: a ```Token``` for terminals, the value of typed rules (or the ```AstNode``` of untyped ones),
+ *    an ```Option``` for ```?``` and ```[ ]```, and a ```Vec``` for repetitions and lists. A group holding a single terminal or rule among
+ *    string and char literals (or a single string or char literal) stands for that item, so ```rest:(',' expr)*``` is a ```Vec``` of ```expr```s.
+ *    Other groups give their children as a ```Vec<AstOrToken>```.
+ *    Labels inside of a group are wrapped the same way, so ```b``` is an ```Option``` in ```a:num [ '+' b:num ]``` and a ```Vec``` in
+ *    ```a:num ( '+' b:num )*```. A label that only some of a group's options have is an ```Option``` too.
+ *    The value is kept on the rule's node and can be read back with ```.value::<Expr>()```, which gives ```None``` if the node has no ```Expr```.
+ *    Actions written with ```=>?``` or ```=>!``` give back a ```Result<Type, E>``` instead, where ```E``` implements ```Display```.
+ *    An ```Err``` from ```=>?``` fails the option, so the next one is tried, while an ```Err``` from ```=>!``` aborts the whole parse.
+ *    Either way, the error is kept in the ```ParserError```, and can be found with ```err.user_error::<E>()```.
+ *  - Parameterised rules: a rule can take rule names as parameters, as in ```list<X> := X (',' X)*;```, and is used by giving it
+ *    arguments: ```args := '(' list<expr> ')';```. Each set of arguments gets a rule of its own, named after the rule and its arguments
+ *    (```list<expr>``` becomes ```list_expr```, with its own ```GrammarToken```). Arguments can use parameterised rules themselves: ```list<pair<key, value>>```.
+ *    If that name is already taken by another rule (say, a rule called ```list_expr```), the parser isn't generated.
+ *  - Dropped elements: ```~item``` matches as normal, but leaves nothing in the tree. Ex: ```stmt := ~"let" name:ident ~'=' value:expr ~';';```
+ *  - Spliced rules: a rule whose name starts with ```~``` (```~ident := #(TokenType::Identifier);```) never gets a node of its own.
+ *    Its children (and labels) are added straight to the rule that used it instead. Spliced rules can't be given a type.
+ *  - Cut: ```^``` commits to the option it is in. If anything after it fails, no other options are tried, and the parse ends with an error
+ *    pointing at the element that failed. Ex: ```item := "fn" ^ name:ident '(' params ')' | stmt;```
+ *  - Expectation labels: ```item @ "a description"``` changes the error given when ```item``` fails to "Expected a description".
+ *    Ex: ```stmt := "let" #(TokenType::Identifier) @ "a variable name" '=' expr @ "an expression";```
+ *  - Error recovery: ending a rule with ```recover_to(...)``` lets the parse carry on when that rule fails, as in
+ *    ```stmt := "let" ^ name:ident '=' expr ';' | expr ';' recover_to(';', '}');```. If the rule fails after getting at least one token in,
+ *    the tracker skips ahead from where it failed to just past the next token listed (or the end of the input), and the rule gives back a node
+ *    holding only the error, found with ```node.error()```. These nodes have no labels or value. Errors from ```=>!``` actions are never recovered from.
+ *    As these nodes have no value, rules with a type can't use ```recover_to```.
+ *  - Lookahead: ```&item``` matches only if ```item``` would match next, and ```!item``` matches only if it would not.
+ *    Neither one consumes any tokens or adds anything to the tree. Ex: ```call := ident &'(' args;```
  *
  * The parser will automatically attempt to match the **first rule in the list** when provided with the Token Tracker.
  *
@@ -61,6 +117,41 @@
  * ```
  *  parser(&mut tracker: &mut TokenTracker) -> Option<AstOrToken>
  * ```
+ *
+ * If the first rule has a type, ```parse_value(&mut tracker) -> Result<Type, ParserError>``` is exposed as well, returning its value.
+ *
+ * Every rule without a type also gets a type of its own in the ```typed``` module, named after the rule (```comma_list``` becomes ```typed::CommaList```).
+ * Rules with one option become a struct, and rules with several become an enum with a variant per option, named after the keyword or first field of the option
+ * (a variant that would be called ```Self``` is called ```Self_``` instead).
+ * Each labelled element becomes a field of the same name, and unlabelled elements are named after the rule they hold (or ```token```). String and char
+ * literals are left out unless labelled. Fields are typed the same way labels are in actions, except that untyped rules give their typed node, so types given
+ * to rules need to implement ```Debug``` and ```Clone```. If the first rule has no type, ```parse_typed(&mut tracker) -> Result<typed::Rule, ParserError>``` is exposed,
+ * and any node can be converted with ```typed::Rule::from_node(&node)```.
+ * Groups are typed the same way as well, except for groups with more than one field or option, which get a type of their own, made the same way
+ * a rule's is and named after the type holding it and the field (unlabelled groups are called ```group```): ```args:( expr ',' expr | '*' )``` in ```call```
+ * is a ```typed::CallArgs``` enum. A group with a single field stands for that field, so ```[ '!' ]``` is an ```Option<Token>```.
+ * Fields holding a rule that uses ```recover_to``` are a ```Result```, which is the error when the rule was recovered from, and
+ * ```parse_typed``` gives back the error when the first rule was.
+ *
+ * ```parse_repaired(&mut tracker) -> Result<(AstOrToken, Vec<ParserError>), ParserError>``` parses the same way, but fixes small mistakes in the input
+ * as it goes. When the parse gets stuck, it tries inserting each terminal that was expected there, and removing the token there, keeping whichever
+ * matches the most terminals before the parse gets stuck again. Each insertion stands in for a single terminal, so one missing twice in a row takes
+ * two of them. String and char literals and plain ```#()``` token literals can be inserted, while lexeme patterns, token patterns and ```.``` can't. Each repair is
+ * given back as an error, such as ```Inserted missing `;` ``` or ```Removed unexpected `=` ```, and at most ```MAX_REPAIRS``` are made.
+ * A repair that stopped mattering once later ones were made (say, an insertion for an option the parse no longer takes) isn't given back.
+ *
+ * To see every error at once, ```parse_all(&mut tracker) -> (Option<AstOrToken>, Vec<ParserError>)``` gives back the repairs it made, the errors
+ * rules recovered from with ```recover_to```, and the error the parse stopped at (if it did), in the order they appear in the input. The tree
+ * is given back whenever the parse gets one, and ```node.errors()``` finds the recovered errors under any node. Repairs go on past
+ * ```MAX_REPAIRS``` for as long as they get the parse further, and where none helps, the token the parse is stuck on is skipped.
+ * Unlike ```parse_repaired```, rules with ```recover_to``` are left to recover rather than repaired, and the parse isn't cut short with
+ * insertions while there is input left, so statements after an error are still parsed.
+ * Each ```ParserError``` has ```.message()``` and ```.line()``` for reporting it.
+ *
+ * When a parse fails, the error points at the furthest token the parse got to, rather than where the first rule started, and names
+ * every terminal that could have come next: ```expected `)` or `,`, found `;` at line 3```. An item with an expectation label is named by its
+ * label instead, if it failed on its first token. The same goes for errors recovered from. Errors from ```=>?``` and ```=>!``` actions are left as they are,
+ * and so are errors from a group matching the wrong number of times (```{n,m}```), unless the parse got further than the group.
  *  
  * # Example Input #
  * ```
@@ -74,16 +165,11 @@
  *
  * # Current Bugs #
  * As hinted at above, there are some issues:
- *  - Code generation gets very confused when mixing some parenthesis with some modifiers, notably the ```*``` with square brackets.
- *  - The markers used to reset the token tracker inside of ```*``` groups can, in rare cases, overwrite one another.
- * This occurs due to the naming scheme using the length of tokens present within the paren group to form the name. In rare cases,
- * this can be equivalent. See ``` test := ((test)*)*; ```
  *  - The Rust compiler throws many warnings when expanding the compiler as each rule is expanded into a enumerated type.
  *  This is purely a cosmetic issue.
  *  - This may not actually be a full PEG parser, as pack-rat parsing has not been implemented. Left-recursion is also impossible, unless you
  * want to wait until the heat death of the universe for the parser to work :(.
  *  - The AstOrToken type is a workaround for allowing either Tokens or AstNodes as children for AstNodes. It's dumb and I hate it.
- *  - It turns out interpolating symbol literals (the #() things) do not work inside of parenthesis groups. Ask me how I know.
  *
  */
 /// *************************************************************************** ///
@@ -0,0 +1,104 @@
+// The expanded parser is noisy (see "Current Bugs" in the README).
+#![allow(warnings, clippy::all)]
+
+mod common;
+
+use common::*;
+
+mod calc {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        expr -> i64 := a:term '+' b:expr => { a + b } | t:term => { t };
+        term -> i64 := n:#(TokenType::Numeric) => { n.lexeme.parse().unwrap() };
+    }
+}
+
+#[test]
+fn actions_build_values() {
+    assert_eq!(calc::parse_value(&mut lex("1 + 2 + 3")).unwrap(), 6);
+}
+
+#[test]
+fn values_are_read_back_by_type() {
+    let tree = calc::parser(&mut lex("1 + 2")).unwrap();
+    assert_eq!(tree.value::<i64>(), Some(3));
+    assert_eq!(tree.value::<String>(), None);
+
+    let node = tree.node();
+    assert_eq!(node.child[0].as_ref().unwrap().value::<i64>(), Some(1));
+    assert_eq!(node.child[1].as_ref().unwrap().value::<i64>(), None);
+}
+
+// Numbers that don't fit in a byte fail the first option, and are caught by the second.
+mod checked {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        stmt -> i64 := n:num ';' => { n } | n:big ';' => { n };
+        num -> i64 := n:#(TokenType::Numeric) =>? {
+            n.lexeme.parse::<u8>().map(i64::from).map_err(|_| format!("{} doesn't fit in a byte", n.lexeme))
+        };
+        big -> i64 := n:#(TokenType::Numeric) '!' => { n.lexeme.parse().unwrap() };
+    }
+}
+
+mod reserved {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        decl -> String := "let" n:name => { n } | "let" '_' => { "_".to_string() } | "let" n:#(TokenType::Identifier) => { n.lexeme };
+        name -> String := n:#(TokenType::Identifier) =>! {
+            if n.lexeme == "let" { Err("`let` is reserved") } else { Ok(n.lexeme) }
+        };
+    }
+}
+
+// An abort inside of a rule that recovers.
+mod recovering {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        prog := stmt* "end";
+        stmt := "let" name '=' #(TokenType::Numeric) ';' recover_to(';');
+        name -> String := n:#(TokenType::Identifier) =>! {
+            if n.lexeme == "let" { Err("`let` is reserved") } else { Ok(n.lexeme) }
+        };
+    }
+}
+
+#[test]
+fn fallible_actions_fail_the_option() {
+    assert_eq!(checked::parse_value(&mut lex("7 ;")).unwrap(), 7);
+    assert_eq!(checked::parse_value(&mut lex("300 ! ;")).unwrap(), 300);
+
+    let err = checked::parse_value(&mut lex("300 ;")).unwrap_err();
+    assert!(!err.is_fatal());
+    assert_eq!(err.user_error::<String>().unwrap(), "300 doesn't fit in a byte");
+    assert!(err.user_error::<&str>().is_none());
+}
+
+#[test]
+fn aborting_actions_end_the_parse() {
+    assert_eq!(reserved::parse_value(&mut lex("let x")).unwrap(), "x");
+
+    // The last option would match, but is never tried.
+    let err = reserved::parse_value(&mut lex("let let")).unwrap_err();
+    assert!(err.is_fatal());
+    assert_eq!(err.message(), "`let` is reserved");
+    assert_eq!(*err.user_error::<&str>().unwrap(), "`let` is reserved");
+}
+
+#[test]
+fn aborts_are_not_recovered_from() {
+    let tree = recovering::parser(&mut lex("let x = 1 ; let 2 = 3 ; end")).unwrap();
+    assert_eq!(tree.node().errors().len(), 1);
+
+    let err = recovering::parser(&mut lex("let x = 1 ; let let = 3 ; end")).unwrap_err();
+    assert!(err.is_fatal());
+    assert!(err.user_error::<&str>().is_some());
+}
@@ -0,0 +1,56 @@
+// The expanded parser is noisy (see "Current Bugs" in the README).
+#![allow(warnings, clippy::all)]
+
+mod common;
+
+use common::*;
+
+mod between {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        items := 'x'{2,3} ';' #(TokenType::EOF);
+    }
+}
+
+mod exactly {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        pairs := ('a' 'b'){2} ';' #(TokenType::EOF);
+    }
+}
+
+#[test]
+fn counts_in_range_match() {
+    assert_eq!(between::parser(&mut lex("x x ;")).unwrap().node().child.len(), 4);
+    assert_eq!(between::parser(&mut lex("x x x ;")).unwrap().node().child.len(), 5);
+    assert_eq!(exactly::parser(&mut lex("a b a b ;")).unwrap().node().child.len(), 6);
+}
+
+#[test]
+fn too_few_matches_name_the_range() {
+    let err = between::parser(&mut lex("x ;")).unwrap_err();
+    assert_eq!(err.message(), "Expected between 2 and 3 matches of group, found 1");
+    assert_eq!(err.line(), 1);
+
+    let err = exactly::parser(&mut lex("a b ;")).unwrap_err();
+    assert_eq!(err.message(), "Expected exactly 2 matches of group, found 1");
+}
+
+#[test]
+fn too_many_matches_name_the_range() {
+    let err = between::parser(&mut lex("x x x x ;")).unwrap_err();
+    assert_eq!(err.message(), "Expected between 2 and 3 matches of group, found 4");
+
+    let err = exactly::parser(&mut lex("a b a b a b ;")).unwrap_err();
+    assert_eq!(err.message(), "Expected exactly 2 matches of group, found 3");
+}
+
+#[test]
+fn looking_past_the_bound_expects_nothing() {
+    let err = between::parser(&mut lex("x x x y")).unwrap_err();
+    assert_eq!(err.message(), "expected `;`, found `y` at line 1");
+}
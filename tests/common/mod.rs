@@ -0,0 +1,87 @@
+//! A small lexer and token tracker for the test grammars. The generated parser
+//! only needs `Token`, `TokenType`, `TokenTracker`, `mark`, `reset` and `get_token`
+//! to be in scope where `peg_parse!` is used.
+#![allow(dead_code)]
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenType {
+    Identifier,
+    Numeric,
+    Comma,
+    Semi,
+    Punct,
+    EOF,
+    Number(f64),
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub lexeme: String,
+    pub identifier: TokenType,
+    pub line: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenTracker {
+    pos: usize,
+    toks: Vec<Token>,
+}
+
+/// Splits the input on whitespace, giving each word a kind and the line it was
+/// found on. An `EOF` token is added to the end.
+pub fn lex(input: &str) -> TokenTracker {
+    let mut toks = vec![];
+    let mut last_line = 1;
+    for (index, line) in input.lines().enumerate() {
+        last_line = index as i32 + 1;
+        for word in line.split_whitespace() {
+            let identifier = if word.contains('.') && word.parse::<f64>().is_ok() {
+                TokenType::Number(word.parse().unwrap())
+            } else if word.chars().all(|c| c.is_ascii_digit()) {
+                TokenType::Numeric
+            } else if word.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                TokenType::Identifier
+            } else if word == "," {
+                TokenType::Comma
+            } else if word == ";" {
+                TokenType::Semi
+            } else {
+                TokenType::Punct
+            };
+            toks.push(Token {
+                lexeme: word.to_string(),
+                identifier,
+                line: last_line,
+            });
+        }
+    }
+    toks.push(Token {
+        lexeme: String::new(),
+        identifier: TokenType::EOF,
+        line: last_line,
+    });
+    TokenTracker { pos: 0, toks }
+}
+
+pub fn mark(tracker: &mut TokenTracker) -> usize {
+    tracker.pos
+}
+
+pub fn reset(tracker: &mut TokenTracker, pos: usize) {
+    tracker.pos = pos;
+}
+
+pub fn get_token(tracker: &mut TokenTracker) -> Result<Token, String> {
+    match tracker.toks.get(tracker.pos) {
+        Some(tok) => {
+            tracker.pos += 1;
+            Ok(tok.clone())
+        }
+        None => Err("End of input".to_string()),
+    }
+}
+
+/// Whether every token was used up, `EOF` included.
+pub fn finished(tracker: &mut TokenTracker) -> bool {
+    tracker.pos == tracker.toks.len()
+}
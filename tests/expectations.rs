@@ -0,0 +1,33 @@
+// The expanded parser is noisy (see "Current Bugs" in the README).
+#![allow(warnings, clippy::all)]
+
+mod common;
+
+use common::*;
+
+mod described {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        stmt := "let" #(TokenType::Identifier) @ "a variable name" '=' expr @ "an expression" ';';
+        expr := #(TokenType::Identifier) | #(TokenType::Numeric) | '(' expr ')';
+    }
+}
+
+#[test]
+fn labels_describe_what_was_expected() {
+    let err = described::parser(&mut lex("let 5 = x ;")).unwrap_err();
+    assert_eq!(err.message(), "expected a variable name, found `5` at line 1");
+    assert_eq!(err.line(), 1);
+}
+
+#[test]
+fn labels_win_over_the_terminals_they_hide() {
+    let err = described::parser(&mut lex("let x = ;")).unwrap_err();
+    assert_eq!(err.message(), "expected an expression, found `;` at line 1");
+
+    // Once the item got past its first token, what it expected there is given instead.
+    let err = described::parser(&mut lex("let x = ( ;")).unwrap_err();
+    assert_eq!(err.message(), "expected Identifier, Numeric or `(`, found `;` at line 1");
+}
@@ -0,0 +1,128 @@
+// The expanded parser is noisy (see "Current Bugs" in the README).
+#![allow(warnings, clippy::all)]
+
+mod common;
+
+use common::*;
+
+mod plus {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        items := ('a' 'b')+ ';' #(TokenType::EOF);
+    }
+}
+
+mod star {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        items := ('a' 'b')* 'a' 'c' #(TokenType::EOF);
+    }
+}
+
+mod optional {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        opt := ['a' 'b'] 'a' 'c' #(TokenType::EOF);
+    }
+}
+
+mod required {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        choice := ('a' 'b') 'c' #(TokenType::EOF) | 'a' 'd' #(TokenType::EOF);
+    }
+}
+
+mod question {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        opt := word? ('-')? ';' #(TokenType::EOF);
+        word := #(TokenType::Identifier);
+    }
+}
+
+// The node a grammar's parser gave back.
+macro_rules! node {
+    ($grammar:ident, $tree:expr) => {
+        match $tree {
+            $grammar::AstOrToken::Ast(node) => node,
+            $grammar::AstOrToken::Tok(tok) => panic!("Expected a node, found `{}`", tok.lexeme),
+        }
+    };
+}
+
+#[test]
+fn plus_needs_one_repetition() {
+    let mut tracker = lex("a b a b ;");
+    let tree = plus::parser(&mut tracker).unwrap();
+    assert_eq!(node!(plus, tree).child.len(), 6);
+    assert!(finished(&mut tracker));
+
+    assert!(plus::parser(&mut lex(";")).is_err());
+}
+
+#[test]
+fn star_gives_back_a_partial_repetition() {
+    let mut tracker = lex("a b a c");
+    let tree = star::parser(&mut tracker).unwrap();
+    let lexemes: Vec<String> = node!(star, tree)
+        .child
+        .iter()
+        .map(|child| match child {
+            Ok(star::AstOrToken::Tok(tok)) => tok.lexeme.clone(),
+            other => panic!("Expected a token, found {:?}", other),
+        })
+        .collect();
+    assert_eq!(lexemes, ["a", "b", "a", "c", ""]);
+}
+
+#[test]
+fn optional_group_matches_all_or_nothing() {
+    let tree = optional::parser(&mut lex("a c")).unwrap();
+    assert_eq!(node!(optional, tree).child.len(), 3);
+
+    let tree = optional::parser(&mut lex("a b a c")).unwrap();
+    assert_eq!(node!(optional, tree).child.len(), 5);
+}
+
+#[test]
+fn failed_group_moves_on_to_the_next_option() {
+    let tree = required::parser(&mut lex("a b c")).unwrap();
+    assert_eq!(node!(required, tree).child.len(), 4);
+
+    let tree = required::parser(&mut lex("a d")).unwrap();
+    assert_eq!(node!(required, tree).child.len(), 3);
+}
+
+#[test]
+fn question_mark_on_an_item_or_group_is_optional() {
+    let tree = question::parser(&mut lex("a - ;")).unwrap();
+    assert_eq!(node!(question, tree).child.len(), 4);
+
+    let tree = question::parser(&mut lex("a ;")).unwrap();
+    assert_eq!(node!(question, tree).child.len(), 3);
+
+    let tree = question::parser(&mut lex("- ;")).unwrap();
+    let node = node!(question, tree);
+    assert_eq!(node.child.len(), 3);
+    assert_eq!(node.child[0].as_ref().unwrap().token().lexeme, "-");
+}
+
+#[test]
+fn question_mark_leaves_no_child_when_nothing_matches() {
+    let tree = question::parser(&mut lex(";")).unwrap();
+    let lexemes: Vec<String> = node!(question, tree).child.iter().map(|child| child.as_ref().unwrap().token().lexeme).collect();
+    assert_eq!(lexemes, [";", ""]);
+
+    assert!(question::parser(&mut lex("a a ;")).is_err());
+}
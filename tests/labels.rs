@@ -0,0 +1,49 @@
+// The expanded parser is noisy (see "Current Bugs" in the README).
+#![allow(warnings, clippy::all)]
+
+mod common;
+
+use common::*;
+
+// Labels inside of groups that might match them many times, or not at all.
+mod grouped {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        top -> i64 := "opt" v:optional => { v } | "many" v:many => { v } | "either" v:either => { v }
+            | "both" v:both => { v } | "list" v:list => { v } | "ahead" v:ahead => { v };
+        optional -> i64 := a:num [ '+' b:num ] ';' => { a + b.unwrap_or(0) };
+        many -> i64 := a:num ( '+' b:num ( '*' c:num )? )* ';' => { a + b.iter().sum::<i64>() + c.iter().sum::<i64>() };
+        either -> i64 := ( '-' a:num | '+' a:num | b:num ) ';' => { a.unwrap_or(0) + b.unwrap_or(0) };
+        both -> i64 := ( '-' a:num | '+' a:num ) ';' => { a };
+        list -> i64 := ( a:num ) % ',' ';' => { a.iter().sum() };
+        ahead -> i64 := &( a:num ) b:num ';' => { a.unwrap_or(0) + b };
+        num -> i64 := n:#(TokenType::Numeric) => { n.lexeme.parse().unwrap() };
+    }
+}
+
+#[test]
+fn optional_labels_are_bound_to_options() {
+    assert_eq!(grouped::parse_value(&mut lex("opt 1 ;")).unwrap(), 1);
+    assert_eq!(grouped::parse_value(&mut lex("opt 1 + 2 ;")).unwrap(), 3);
+}
+
+#[test]
+fn repeated_labels_are_bound_to_lists() {
+    assert_eq!(grouped::parse_value(&mut lex("many 1 ;")).unwrap(), 1);
+    assert_eq!(grouped::parse_value(&mut lex("many 1 + 2 + 3 * 4 ;")).unwrap(), 10);
+    assert_eq!(grouped::parse_value(&mut lex("list 1 , 2 , 3 ;")).unwrap(), 6);
+}
+
+#[test]
+fn labels_missing_from_an_option_are_optional() {
+    assert_eq!(grouped::parse_value(&mut lex("either - 2 ;")).unwrap(), 2);
+    assert_eq!(grouped::parse_value(&mut lex("either 5 ;")).unwrap(), 5);
+    assert_eq!(grouped::parse_value(&mut lex("both + 7 ;")).unwrap(), 7);
+}
+
+#[test]
+fn labels_inside_lookahead_are_empty() {
+    assert_eq!(grouped::parse_value(&mut lex("ahead 4 ;")).unwrap(), 4);
+}
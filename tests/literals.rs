@@ -0,0 +1,53 @@
+// The expanded parser is noisy (see "Current Bugs" in the README).
+#![allow(warnings, clippy::all)]
+
+mod common;
+
+use common::*;
+
+mod numbers {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        nums := 0 0x1F 1.5 1e3 #(TokenType::EOF);
+    }
+}
+
+mod bytes {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        bytes := b"let" b'=' b"\x41\x42" b"\\x80" br"\n" #(TokenType::EOF);
+    }
+}
+
+mod raw {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        raw := r"\n" r#""x""# r#""*""#g #(TokenType::EOF);
+    }
+}
+
+#[test]
+fn numbers_match_as_written() {
+    assert!(numbers::parser(&mut lex("0 0x1F 1.5 1e3")).is_ok());
+    assert!(numbers::parser(&mut lex("0 31 1.5 1e3")).is_err());
+    assert!(numbers::parser(&mut lex("0 0x1F 1.50 1e3")).is_err());
+}
+
+#[test]
+fn byte_strings_match_the_text_they_hold() {
+    assert!(bytes::parser(&mut lex("let = AB \\x80 \\n")).is_ok());
+    assert!(bytes::parser(&mut lex("let = \\x41\\x42 \\x80 \\n")).is_err());
+}
+
+#[test]
+fn raw_strings_keep_their_quotes() {
+    assert!(raw::parser(&mut lex("\\n \"x\" \"y\"")).is_ok());
+    assert!(raw::parser(&mut lex("\\n x \"y\"")).is_err());
+    assert!(raw::parser(&mut lex("\\n \"x\" y")).is_err());
+}
@@ -0,0 +1,75 @@
+// The expanded parser is noisy (see "Current Bugs" in the README).
+#![allow(warnings, clippy::all)]
+
+mod common;
+
+use common::*;
+
+// Statements that are recovered from, used from the typed tree and from an action.
+mod stmts {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        prog := stmt* "end" #(TokenType::EOF);
+        stmt := "let" #(TokenType::Identifier) '=' #(TokenType::Identifier) ';' recover_to(';');
+    }
+}
+
+mod counted {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        prog -> usize := s:stmt* "end" #(TokenType::EOF) => { s.iter().filter(|s| s.error().is_none()).count() };
+        stmt := "let" #(TokenType::Identifier) '=' #(TokenType::Identifier) ';' recover_to(';');
+    }
+}
+
+// The first rule recovering from an error itself.
+mod top {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        stmt := "let" #(TokenType::Identifier) '=' #(TokenType::Identifier) ';' recover_to(';');
+    }
+}
+
+#[test]
+fn recovered_rules_are_results_in_the_typed_tree() {
+    let typed = stmts::parse_typed(&mut lex("let a = b ; let c = = ; end")).unwrap();
+    assert_eq!(typed.stmt.len(), 2);
+    assert_eq!(typed.stmt[0].as_ref().unwrap().token.lexeme, "a");
+    assert!(typed.stmt[1].is_err());
+}
+
+#[test]
+fn recovered_rules_can_be_used_in_actions() {
+    assert_eq!(counted::parse_value(&mut lex("let a = b ; let c = = ; end")).unwrap(), 1);
+}
+
+#[test]
+fn a_recovered_first_rule_is_an_error() {
+    assert!(top::parse_typed(&mut lex("let c = = ;")).is_err());
+    assert!(top::parse_typed(&mut lex("let c = d ;")).is_ok());
+}
+
+#[test]
+fn parse_all_leaves_recovering_to_the_rules() {
+    let (tree, errors) = stmts::parse_all(&mut lex("let x = y ; let 5 = ; let z = w ; end"));
+    let messages: Vec<&str> = errors.iter().map(|err| err.message()).collect();
+    assert_eq!(messages, ["expected Identifier, found `5` at line 1"]);
+    // Three statements, `end` and EOF: the one after the error isn't given up.
+    assert_eq!(tree.unwrap().node().child.len(), 5);
+}
+
+#[test]
+fn parse_all_keeps_what_parses_after_a_skip() {
+    let (tree, errors) = stmts::parse_all(&mut lex("let x = y ; 5 ; let z = w ; end"));
+    assert!(errors[0].message().starts_with("expected `let` or `end`, found `5`"));
+    let node = tree.unwrap().node();
+    assert_eq!(node.child.len(), 4);
+    let second = node.child[1].as_ref().unwrap().node();
+    assert_eq!(second.child[1].as_ref().unwrap().token().lexeme, "z");
+}
@@ -0,0 +1,128 @@
+// The expanded parser is noisy (see "Current Bugs" in the README).
+#![allow(warnings, clippy::all)]
+
+mod common;
+
+use common::*;
+
+// The same terminal missing twice in a row.
+mod twice {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        prog := '(' ';' ';' ')' #(TokenType::EOF);
+    }
+}
+
+// An insertion used by an option that fails, and then by the next one.
+mod backtracked {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        prog := first | second;
+        first := ';' "x" #(TokenType::EOF);
+        second := ';' "y" #(TokenType::EOF);
+    }
+}
+
+// More missing terminals than `MAX_REPAIRS`.
+mod statements {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        prog := stmt* #(TokenType::EOF);
+        stmt := #(TokenType::Identifier) '=' #(TokenType::Numeric) ';';
+    }
+}
+
+// Nothing here can be inserted, so tokens the parse can't use have to be skipped.
+mod patterns {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        prog := item* "end"g;
+        item := "a*"g "=*"g;
+    }
+}
+
+// An insertion made for `second`, which stops mattering once removing the `n` lets `first` match.
+mod abandoned {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        prog := first | second;
+        first := '(' !( . 'n' ) 'm' #(TokenType::EOF);
+        second := '(' 'x' 'm' 'q' #(TokenType::EOF);
+    }
+}
+
+fn tokens(node: &twice::AstNode) -> Vec<String> {
+    node.child
+        .iter()
+        .map(|child| child.as_ref().unwrap().token().lexeme)
+        .collect()
+}
+
+#[test]
+fn each_insertion_stands_in_for_one_terminal() {
+    let (tree, fixes) = twice::parse_repaired(&mut lex("( )")).unwrap();
+    let messages: Vec<String> = fixes.iter().map(|fix| format!("{:?}", fix)).collect();
+    assert_eq!(fixes.len(), 2, "{:?}", messages);
+    assert!(messages.iter().all(|message| message.contains("Inserted missing `;`")));
+
+    assert_eq!(tokens(&tree.node()), vec!["(", ";", ";", ")", ""]);
+
+    let (_, fixes) = twice::parse_repaired(&mut lex("( ; )")).unwrap();
+    assert_eq!(fixes.len(), 1);
+
+    let (tree, fixes) = twice::parse_repaired(&mut lex("( ; x ; )")).unwrap();
+    assert_eq!(fixes.len(), 1);
+    assert!(format!("{:?}", fixes[0]).contains("Removed unexpected `x`"));
+    assert_eq!(tokens(&tree.node()), vec!["(", ";", ";", ")", ""]);
+}
+
+#[test]
+fn backing_up_gives_insertions_back() {
+    let (tree, fixes) = backtracked::parse_repaired(&mut lex("y")).unwrap();
+    assert_eq!(fixes.len(), 1);
+    assert_eq!(tree.node().option, 1);
+}
+
+#[test]
+fn parse_all_keeps_repairing_past_the_limit() {
+    let input = "a = 1 ".repeat(15);
+    assert!(statements::parse_repaired(&mut lex(&input)).is_err());
+
+    let (tree, errors) = statements::parse_all(&mut lex(&input));
+    assert_eq!(errors.len(), 15);
+    assert!(errors.iter().all(|err| err.message() == "Inserted missing `;`"));
+    assert_eq!(tree.unwrap().node().child.len(), 16);
+}
+
+#[test]
+fn parse_all_skips_what_it_cannot_repair() {
+    let (tree, errors) = patterns::parse_all(&mut lex("ab = x x x ac = end"));
+    assert_eq!(tree.unwrap().node().child.len(), 3);
+    assert!(errors[0].message().starts_with("expected a lexeme like `a*` or a lexeme like `end`, found `x`"));
+
+    let (tree, errors) = patterns::parse_all(&mut lex("ab = x"));
+    assert!(tree.is_none());
+    assert_eq!(errors.len(), 2);
+}
+
+#[test]
+fn repairs_the_parse_stopped_using_are_not_reported() {
+    let (tree, fixes) = abandoned::parse_repaired(&mut lex("( m n")).unwrap();
+    assert_eq!(tree.node().option, 0);
+    let messages: Vec<&str> = fixes.iter().map(|fix| fix.message()).collect();
+    assert_eq!(messages, ["Removed unexpected `n`"]);
+
+    let (_, errors) = abandoned::parse_all(&mut lex("( m n"));
+    let messages: Vec<&str> = errors.iter().map(|err| err.message()).collect();
+    assert_eq!(messages, ["Removed unexpected `n`"]);
+}
@@ -0,0 +1,68 @@
+// The expanded parser is noisy (see "Current Bugs" in the README).
+#![allow(warnings, clippy::all)]
+
+mod common;
+
+use common::*;
+
+// Rule names that are also the names of variables in the generated code.
+mod names {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        top := item* #(TokenType::EOF);
+        item := value | node | label | pattern | found | state | e | c | name | tree | child
+            | "bad" ^ ';' recover_to(';');
+        value := "v" ';';
+        node := "n" ';';
+        label := "l" ';';
+        pattern := "p*"g ';';
+        found := "f" ';';
+        state := "s" ';';
+        e := "e" ';';
+        c := "c" ';';
+        name := #(TokenType::Numeric | TokenType::Number(_)) ';';
+        tree := "t" ';';
+        child := "ch" ';';
+    }
+}
+
+// Labels that are also the names of variables in the generated code.
+mod actions {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        value -> i64 := node:num rest:num* #(TokenType::EOF) => { node + rest.iter().sum::<i64>() };
+        num -> i64 := state:#(TokenType::Numeric) => { state.lexeme.parse().unwrap() };
+    }
+}
+
+#[test]
+fn rules_can_share_names_with_generated_variables() {
+    let input = "v ; n ; l ; pat ; f ; s ; e ; c ; 1 ; 1.5 ; t ; ch ;";
+    let tree = names::parser(&mut lex(input)).unwrap();
+    match tree {
+        names::AstOrToken::Ast(node) => assert_eq!(node.child.len(), 13),
+        names::AstOrToken::Tok(tok) => panic!("Expected a node, found `{}`", tok.lexeme),
+    }
+
+    let typed = names::parse_typed(&mut lex(input)).unwrap();
+    assert_eq!(typed.item.len(), 12);
+
+    let tree = names::parser(&mut lex("v ; bad x ;")).unwrap();
+    let bad = match &tree.node().child[1] {
+        Ok(names::AstOrToken::Ast(node)) => node.clone(),
+        other => panic!("Expected a node, found {:?}", other),
+    };
+    assert!(bad.error().is_some());
+
+    let (_, errors) = names::parse_all(&mut lex("v ; bad x ;"));
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn labels_can_share_names_with_generated_variables() {
+    assert_eq!(actions::parse_value(&mut lex("1 2 3")).unwrap(), 6);
+}
@@ -0,0 +1,30 @@
+// The expanded parser is noisy (see "Current Bugs" in the README).
+#![allow(warnings, clippy::all)]
+
+mod common;
+
+use common::*;
+
+// A spliced rule used before a field of the rule using it.
+mod fields {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        prog := wrap num;
+        ~wrap := first:num num;
+        num := #(TokenType::Numeric);
+    }
+}
+
+#[test]
+fn spliced_rules_keep_their_fields_to_themselves() {
+    let typed = fields::parse_typed(&mut lex("1 2 3")).unwrap();
+    assert_eq!(typed.num.token.lexeme, "3");
+    assert_eq!(typed.children.len(), 2);
+
+    let tree = fields::parser(&mut lex("1 2 3")).unwrap();
+    let node = tree.node();
+    assert_eq!(node.child.len(), 3);
+    assert_eq!(node.get("first").unwrap().node().child[0].as_ref().unwrap().token().lexeme, "1");
+}
@@ -0,0 +1,50 @@
+// The expanded parser is noisy (see "Current Bugs" in the README).
+#![allow(warnings, clippy::all)]
+
+mod common;
+
+use common::*;
+
+// Instances used from more than one place, inside of each other, and with
+// arguments whose names have underscores in them.
+mod instances {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        top := (item ';')* #(TokenType::EOF);
+        item := "a" p:pair<num_lit, word> | "b" q:pair<num_lit, word> | "c" r:list<pair<num_lit, word>>;
+        pair<X, Y> := X Y;
+        list<X> := X (',' X)*;
+        num_lit := #(TokenType::Numeric);
+        word := #(TokenType::Identifier);
+    }
+}
+
+fn rule_name(node: &instances::AstNode) -> String {
+    format!("{:?}", node.Type)
+}
+
+#[test]
+fn instances_are_shared_and_nest() {
+    let tree = instances::parser(&mut lex("a 1 x ; b 2 y ; c 3 z , 4 w ;")).unwrap();
+    let items: Vec<_> = tree
+        .node()
+        .child
+        .into_iter()
+        .filter_map(|c| match c {
+            Ok(instances::AstOrToken::Ast(node)) => Some(node),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(items.len(), 3);
+
+    let a = items[0].get("p").unwrap().node();
+    let b = items[1].get("q").unwrap().node();
+    assert_eq!(rule_name(&a), "pair_num_lit_word");
+    assert_eq!(rule_name(&b), "pair_num_lit_word");
+
+    let c = items[2].get("r").unwrap().node();
+    assert_eq!(rule_name(&c), "list_pair_num_lit_word");
+    assert_eq!(c.child.len(), 3);
+}
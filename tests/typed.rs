@@ -0,0 +1,74 @@
+// The expanded parser is noisy (see "Current Bugs" in the README).
+#![allow(warnings, clippy::all)]
+
+mod common;
+
+use common::*;
+
+// Groups of every shape, and a keyword that would name a variant `Self`.
+mod shapes {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        stmt := recv '.' #(TokenType::Identifier) [ '!' ] call:( '(' args:expr % ',' ')' | '[' index:expr ']' )
+            settings:( key:#(TokenType::Identifier) how:( '=' value:expr | ~'?' ) )* ( '+' #(TokenType::Numeric)? )* ';';
+        recv := "self" | #(TokenType::Identifier);
+        expr := #(TokenType::Identifier) | #(TokenType::Numeric);
+    }
+}
+
+use shapes::typed::{Recv, StmtCall, StmtSettings, StmtSettingsHow};
+
+#[test]
+fn reserved_keywords_are_escaped_in_variant_names() {
+    let typed = shapes::parse_typed(&mut lex("self . go ( ) ;")).unwrap();
+    assert!(matches!(*typed.recv, Recv::Self_ {}));
+
+    let typed = shapes::parse_typed(&mut lex("me . go ( ) ;")).unwrap();
+    assert!(matches!(*typed.recv, Recv::Token { ref token } if token.lexeme == "me"));
+}
+
+#[test]
+fn a_single_literal_in_a_group_is_a_token() {
+    let typed = shapes::parse_typed(&mut lex("self . go ! ( ) ;")).unwrap();
+    assert_eq!(typed.token.lexeme, "go");
+    assert_eq!(typed.token_2.unwrap().lexeme, "!");
+
+    let typed = shapes::parse_typed(&mut lex("self . go ( ) ;")).unwrap();
+    assert!(typed.token_2.is_none());
+}
+
+#[test]
+fn groups_with_several_options_are_enums() {
+    let typed = shapes::parse_typed(&mut lex("self . go ( a , 1 ) ;")).unwrap();
+    match typed.call {
+        StmtCall::Args { args } => assert_eq!(args.len(), 2),
+        other => panic!("Expected arguments, found {:?}", other),
+    }
+
+    let typed = shapes::parse_typed(&mut lex("self . go [ 3 ] ;")).unwrap();
+    assert!(matches!(typed.call, StmtCall::Index { .. }));
+}
+
+#[test]
+fn groups_with_several_fields_are_structs() {
+    let typed = shapes::parse_typed(&mut lex("self . go ( ) x = 2 y ? z = w ;")).unwrap();
+    let keys: Vec<&str> = typed.settings.iter().map(|setting| setting.key.lexeme.as_str()).collect();
+    assert_eq!(keys, ["x", "y", "z"]);
+
+    // The middle setting matched no children at all, which still counts as a match.
+    let how: Vec<bool> = typed
+        .settings
+        .iter()
+        .map(|StmtSettings { how, .. }| matches!(how, StmtSettingsHow::Value { .. }))
+        .collect();
+    assert_eq!(how, [true, false, true]);
+}
+
+#[test]
+fn groups_with_one_field_stand_for_it() {
+    let typed = shapes::parse_typed(&mut lex("self . go ( ) + 1 + ;")).unwrap();
+    let numbers: Vec<Option<String>> = typed.group.into_iter().map(|number| number.map(|token| token.lexeme)).collect();
+    assert_eq!(numbers, [Some("1".to_string()), None]);
+}
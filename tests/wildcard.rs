@@ -0,0 +1,45 @@
+// The expanded parser is noisy (see "Current Bugs" in the README).
+#![allow(warnings, clippy::all)]
+
+mod common;
+
+use common::*;
+
+// Skipping everything up to a `;`.
+mod skip {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        skip := (!';' .)* ';';
+    }
+}
+
+mod pair {
+    use crate::common::*;
+    use parser_macros::peg_parse;
+
+    peg_parse! {
+        pair := . .;
+    }
+}
+
+#[test]
+fn wildcard_matches_any_token() {
+    let mut tracker = lex("let x = ( 1 , y ) ;");
+    let tree = skip::parser(&mut tracker).unwrap();
+    let lexemes: Vec<String> = tree.node().child.iter().map(|child| child.as_ref().unwrap().token().lexeme).collect();
+    assert_eq!(lexemes, ["let", "x", "=", "(", "1", ",", "y", ")", ";"]);
+    assert_eq!(mark(&mut tracker), 9);
+}
+
+#[test]
+fn wildcard_fails_at_the_end_of_input() {
+    // The lexer's `EOF` token is a token like any other, but nothing comes after it.
+    let mut tracker = lex("");
+    assert!(pair::parser(&mut tracker).is_err());
+    assert_eq!(mark(&mut tracker), 0);
+
+    assert!(pair::parser(&mut lex("a")).is_ok());
+    assert!(skip::parser(&mut lex("a b")).is_err());
+}